@@ -13,6 +13,7 @@ fn main() {
                     .into_iter()
                     .map(str::to_string)
                     .collect(),
+                ..Default::default()
             }],
             ..Default::default()
         },
@@ -23,10 +24,12 @@ fn main() {
                     .into_iter()
                     .map(str::to_string)
                     .collect(),
+                ..Default::default()
             }),
             win: RunCommand::Custom(Command {
                 program: "cargo".to_string(),
                 args: vec![],
+                ..Default::default()
             }),
             ..Default::default()
         },
@@ -34,9 +37,11 @@ fn main() {
             global: vec![Command {
                 program: "cargo".to_string(),
                 args: ["--version"].into_iter().map(str::to_string).collect(),
+                ..Default::default()
             }],
             ..Default::default()
         },
+        ..Default::default()
     };
 
     println!(
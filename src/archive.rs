@@ -0,0 +1,242 @@
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use xz2::read::XzDecoder;
+
+use crate::manifest::find_manifest;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("could not download {0}")]
+    Download(String, #[source] Box<ureq::Error>),
+    #[error("unsupported archive {0}, expected .tar.gz, .tar.xz or .zip")]
+    UnknownKind(String),
+    #[error("invalid zip archive")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("checksum mismatch (expected {expected}, got {actual})")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("archive contains no manifest")]
+    NoManifest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    TarGz,
+    TarXz,
+    Zip,
+}
+
+impl ArchiveKind {
+    const EXTENSIONS: &'static [(&'static str, ArchiveKind)] = &[
+        (".tar.gz", ArchiveKind::TarGz),
+        (".tgz", ArchiveKind::TarGz),
+        (".tar.xz", ArchiveKind::TarXz),
+        (".txz", ArchiveKind::TarXz),
+        (".zip", ArchiveKind::Zip),
+    ];
+
+    /// Detects the archive kind from the end of a file name or URL
+    pub fn detect(location: &str) -> Option<Self> {
+        let location = strip_query(location);
+        Self::EXTENSIONS
+            .iter()
+            .find(|(ext, _)| location.ends_with(ext))
+            .map(|(_, kind)| *kind)
+    }
+
+    /// File name of `location` without the archive extension, e.g. `tool-1.0` for `.../tool-1.0.tar.gz`
+    pub fn stem(location: &str) -> &str {
+        let location = strip_query(location);
+        let name = location.rsplit(['/', '\\']).next().unwrap_or(location);
+        Self::EXTENSIONS
+            .iter()
+            .find_map(|(ext, _)| name.strip_suffix(ext))
+            .unwrap_or(name)
+    }
+}
+
+fn strip_query(location: &str) -> &str {
+    location.split(['?', '#']).next().unwrap_or(location)
+}
+
+pub fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Opens the archive at `location`, downloading it first if it's an URL
+fn open(location: &str) -> Result<File, ArchiveError> {
+    if !is_url(location) {
+        return Ok(File::open(location)?);
+    }
+
+    let response = ureq::get(location)
+        .call()
+        .map_err(|e| ArchiveError::Download(location.to_string(), Box::new(e)))?;
+    let mut file = tempfile::tempfile()?;
+    std::io::copy(&mut response.into_reader(), &mut file)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+fn sha256(file: &mut File) -> Result<String, std::io::Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(file, &mut hasher)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Fetches the archive at `location` (a path or an HTTP(S) URL) and unpacks it into `dir`.
+///
+/// If `sha256` is given, the archive must match it. Returns the directory inside `dir`
+/// holding the manifest, which is `dir` itself or its only top-level folder.
+pub fn fetch_and_unpack(
+    location: &str,
+    sha256_pin: Option<&str>,
+    dir: &Path,
+) -> Result<PathBuf, ArchiveError> {
+    let kind = ArchiveKind::detect(location)
+        .ok_or_else(|| ArchiveError::UnknownKind(location.to_string()))?;
+    let mut file = open(location)?;
+
+    if let Some(expected) = sha256_pin {
+        let actual = sha256(&mut file)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(ArchiveError::ChecksumMismatch {
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+    }
+
+    std::fs::create_dir_all(dir)?;
+    match kind {
+        ArchiveKind::TarGz => tar::Archive::new(GzDecoder::new(file)).unpack(dir)?,
+        ArchiveKind::TarXz => tar::Archive::new(XzDecoder::new(file)).unpack(dir)?,
+        ArchiveKind::Zip => zip::ZipArchive::new(file)?.extract(dir)?,
+    }
+
+    package_root(dir)
+}
+
+/// Finds the directory with the manifest, looking into a single top-level folder too
+fn package_root(dir: &Path) -> Result<PathBuf, ArchiveError> {
+    if find_manifest(dir).is_some() {
+        return Ok(dir.to_path_buf());
+    }
+
+    let entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    match entries.as_slice() {
+        [single] if single.file_type()?.is_dir() && find_manifest(&single.path()).is_some() => {
+            Ok(single.path())
+        }
+        _ => Err(ArchiveError::NoManifest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    fn tar_gz(prefix: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        let manifest = b"name: x";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, format!("{prefix}run.yml"), &manifest[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// Serves `body` to a single HTTP request, returning the URL
+    fn serve_once(body: Vec<u8>, name: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/{name}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn kind_and_stem() {
+        assert_eq!(
+            ArchiveKind::detect("a/tool.tar.gz"),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(
+            ArchiveKind::detect("https://x.org/tool.tar.xz?dl=1"),
+            Some(ArchiveKind::TarXz)
+        );
+        assert_eq!(ArchiveKind::detect("tool.zip"), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::detect("tool.rar"), None);
+        assert_eq!(
+            ArchiveKind::stem("https://x.org/dl/tool-1.0.tar.gz"),
+            "tool-1.0"
+        );
+    }
+
+    #[test]
+    fn unpack_from_http_with_top_level_folder() {
+        let tmp = tempfile::tempdir().unwrap();
+        let url = serve_once(tar_gz("tool-1.0/"), "tool-1.0.tar.gz");
+
+        let root = fetch_and_unpack(&url, None, tmp.path()).unwrap();
+        assert_eq!(root, tmp.path().join("tool-1.0"));
+    }
+
+    #[test]
+    fn unpack_local_zip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("tool.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("run.yml", Default::default()).unwrap();
+        zip.write_all(b"name: x").unwrap();
+        zip.finish().unwrap();
+
+        let dest = tmp.path().join("dest");
+        let root = fetch_and_unpack(path.to_str().unwrap(), None, &dest).unwrap();
+        assert_eq!(root, dest);
+    }
+
+    #[test]
+    fn sha256_pin() {
+        let tmp = tempfile::tempdir().unwrap();
+        let archive = tar_gz("");
+        let expected = format!("{:x}", Sha256::digest(&archive));
+
+        let url = serve_once(archive.clone(), "tool.tar.gz");
+        assert!(fetch_and_unpack(&url, Some(&expected), &tmp.path().join("a")).is_ok());
+
+        let url = serve_once(archive, "tool.tar.gz");
+        assert!(matches!(
+            fetch_and_unpack(&url, Some(&"0".repeat(64)), &tmp.path().join("b")),
+            Err(ArchiveError::ChecksumMismatch { .. })
+        ));
+    }
+}
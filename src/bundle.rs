@@ -0,0 +1,260 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Component, Path},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::state::InstallRecord;
+
+/// Version of the bundle layout, bumped on incompatible changes
+pub const BUNDLE_VERSION: u32 = 1;
+
+const INFO_ENTRY: &str = "bundle.yml";
+const PAYLOAD_ENTRY: &str = "package.tar";
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("invalid bundle info")]
+    Info(#[from] serde_yaml::Error),
+    #[error("bundle has no {0}")]
+    MissingEntry(&'static str),
+    #[error("unsupported bundle version {0}, expected {BUNDLE_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("checksum mismatch, bundle is corrupted (expected {expected}, got {actual})")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("invalid package id `{0}` in the bundle")]
+    InvalidId(String),
+}
+
+/// Describes the bundled package, stored next to the packed checkout.
+///
+/// A bundle is a `.tar.gz` with two entries: `bundle.yml` holding this struct and
+/// `package.tar` with the checkout, including its `.git` directory and the manifest.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+pub struct BundleInfo {
+    pub version: u32,
+    pub record: InstallRecord, // Install record of the exported package
+    pub sha256: String,        // Checksum of the record as YAML followed by `package.tar`
+}
+
+fn sha256(record: &InstallRecord, file: &mut File) -> Result<String, BundleError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut hasher = Sha256::new();
+    hasher.update(serde_yaml::to_string(record)?);
+    std::io::copy(file, &mut hasher)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Packs the checkout in `dir` together with its install record into `output`
+pub fn pack(dir: &Path, record: InstallRecord, output: &Path) -> Result<BundleInfo, BundleError> {
+    let mut payload = tempfile::tempfile()?;
+    {
+        let mut builder = tar::Builder::new(&mut payload);
+        builder.follow_symlinks(false);
+        builder.append_dir_all(".", dir)?;
+        builder.finish()?;
+    }
+
+    let info = BundleInfo {
+        version: BUNDLE_VERSION,
+        sha256: sha256(&record, &mut payload)?,
+        record,
+    };
+    let info_text = serde_yaml::to_string(&info)?;
+
+    let mut archive = tar::Builder::new(GzEncoder::new(
+        File::create(output)?,
+        Compression::default(),
+    ));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(info_text.len() as u64);
+    header.set_mode(0o644);
+    archive.append_data(&mut header, INFO_ENTRY, info_text.as_bytes())?;
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(payload.metadata()?.len());
+    header.set_mode(0o644);
+    archive.append_data(&mut header, PAYLOAD_ENTRY, &mut payload)?;
+
+    archive.into_inner()?.finish()?;
+    Ok(info)
+}
+
+/// Reads the bundle at `path`, verifies its checksum and unpacks the checkout into `dir`
+pub fn unpack(path: &Path, dir: &Path) -> Result<BundleInfo, BundleError> {
+    let info = read_info(path)?;
+
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(path)?));
+    let mut payload = tempfile::tempfile()?;
+    let mut found = false;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_os_str() == PAYLOAD_ENTRY {
+            std::io::copy(&mut entry, &mut payload)?;
+            found = true;
+            break;
+        }
+    }
+
+    if !found {
+        return Err(BundleError::MissingEntry(PAYLOAD_ENTRY));
+    }
+
+    let actual = sha256(&info.record, &mut payload)?;
+    if actual != info.sha256 {
+        return Err(BundleError::ChecksumMismatch {
+            expected: info.sha256,
+            actual,
+        });
+    }
+
+    std::fs::create_dir_all(dir)?;
+    tar::Archive::new(payload).unpack(dir)?;
+    Ok(info)
+}
+
+/// Reads only the info of the bundle at `path`
+pub fn read_info(path: &Path) -> Result<BundleInfo, BundleError> {
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(path)?));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_os_str() == INFO_ENTRY {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            let info: BundleInfo = serde_yaml::from_str(&text)?;
+            if info.version != BUNDLE_VERSION {
+                return Err(BundleError::UnsupportedVersion(info.version));
+            }
+            if !is_valid_id(&info.record.id) {
+                return Err(BundleError::InvalidId(info.record.id));
+            }
+
+            return Ok(info);
+        }
+    }
+
+    Err(BundleError::MissingEntry(INFO_ENTRY))
+}
+
+/// Whether `id` is `user/name`, two plain path components that stay inside the repos directory
+fn is_valid_id(id: &str) -> bool {
+    let components: Vec<_> = Path::new(id).components().collect();
+    components.len() == 2
+        && components.iter().all(|c| match c {
+            Component::Normal(name) => !name.to_string_lossy().starts_with('.'),
+            _ => false,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SourceRecord;
+
+    #[test]
+    fn pack_unpack() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        std::fs::create_dir_all(src.join("sub")).unwrap();
+        std::fs::write(src.join("run.yml"), "name: x").unwrap();
+        std::fs::write(src.join("sub/file"), "content").unwrap();
+
+        let record = InstallRecord::new(
+            "user/pkg".to_string(),
+            SourceRecord::Git("github.com:user/pkg".to_string()),
+            None,
+        );
+        let bundle = tmp.path().join("pkg.tar.gz");
+        let packed = pack(&src, record, &bundle).unwrap();
+
+        let dest = tmp.path().join("dest");
+        let unpacked = unpack(&bundle, &dest).unwrap();
+        assert_eq!(packed, unpacked);
+        assert_eq!(
+            std::fs::read_to_string(dest.join("run.yml")).unwrap(),
+            "name: x"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dest.join("sub/file")).unwrap(),
+            "content"
+        );
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(src.join("run.yml"), "name: x").unwrap();
+
+        let record = InstallRecord::new(
+            "user/pkg".to_string(),
+            SourceRecord::Local(src.clone()),
+            None,
+        );
+        let bundle = tmp.path().join("pkg.tar.gz");
+        let mut info = pack(&src, record, &bundle).unwrap();
+
+        // Rewrite the bundle with an edited record, keeping the checksum
+        info.record.commit = Some("0".repeat(40));
+        let info_text = serde_yaml::to_string(&info).unwrap();
+        let mut original = tar::Archive::new(GzDecoder::new(File::open(&bundle).unwrap()));
+        let tampered = tmp.path().join("tampered.tar.gz");
+        let mut archive = tar::Builder::new(GzEncoder::new(
+            File::create(&tampered).unwrap(),
+            Compression::default(),
+        ));
+        for entry in original.entries().unwrap() {
+            let entry = entry.unwrap();
+            let mut header = entry.header().clone();
+            if entry.path().unwrap().as_os_str() == INFO_ENTRY {
+                header.set_size(info_text.len() as u64);
+                header.set_cksum();
+                archive.append(&header, info_text.as_bytes()).unwrap();
+            } else {
+                archive.append(&header, entry).unwrap();
+            }
+        }
+        archive.into_inner().unwrap().finish().unwrap();
+
+        assert!(matches!(
+            unpack(&tampered, &tmp.path().join("dest")),
+            Err(BundleError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn traversal_id() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(src.join("run.yml"), "name: x").unwrap();
+
+        for id in [
+            "../../etc",
+            "/abs/pkg",
+            "user/../pkg",
+            "user/pkg/more",
+            "pkg",
+            ".tmp/pkg",
+        ] {
+            let record = InstallRecord::new(id.to_string(), SourceRecord::Local(src.clone()), None);
+            let bundle = tmp.path().join("pkg.tar.gz");
+            pack(&src, record, &bundle).unwrap();
+            assert!(
+                matches!(read_info(&bundle), Err(BundleError::InvalidId(_))),
+                "{id}"
+            );
+        }
+        assert!(is_valid_id("user/pkg@v1"));
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use git2::{Direction, Repository};
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CacheError {
+    #[error("{0} is not cached and network access is disabled")]
+    NotCached(String),
+    #[error("could not update mirror of {0}")]
+    Git(String, #[source] git2::Error),
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+}
+
+/// Information about a single mirror, as shown by `cache list`
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorInfo {
+    pub url: String,
+    pub path: PathBuf,
+    pub size: u64,       // Size on disk in bytes
+    pub fetched_at: u64, // Unix timestamp of the last fetch
+}
+
+/// Shared bare mirrors of remotes, installs are cloned from them instead of the network
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    offline: bool, // Use only what is already cached
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>, offline: bool) -> Self {
+        Self {
+            dir: dir.into(),
+            offline,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Where the mirror of `url` lives, e.g. `<cache>/github.com/user/name.git`
+    pub fn mirror_path(&self, url: &str) -> PathBuf {
+        let path = url.split_once("://").map(|(_, p)| p).unwrap_or(url);
+        let path = path.trim_start_matches('/').trim_end_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path).replace(':', "/");
+        self.dir.join(format!("{path}.git"))
+    }
+
+    /// Returns the mirror of `url`, creating it or fetching new commits into it.
+    ///
+    /// When offline, the mirror is returned as is and must already exist.
+    pub fn mirror(&self, url: &str) -> Result<PathBuf, CacheError> {
+        let path = self.mirror_path(url);
+        let git_err = |e| CacheError::Git(url.to_string(), e);
+
+        if path.exists() {
+            if !self.offline {
+                let repo = Repository::open_bare(&path).map_err(git_err)?;
+                fetch(&repo).map_err(git_err)?;
+            }
+            return Ok(path);
+        }
+
+        if self.offline {
+            return Err(CacheError::NotCached(url.to_string()));
+        }
+
+        std::fs::create_dir_all(&path)?;
+        let result = Repository::init_bare(&path).and_then(|repo| {
+            repo.remote_with_fetch("origin", url, "+refs/heads/*:refs/heads/*")?;
+            repo.remote_add_fetch("origin", "+refs/tags/*:refs/tags/*")?;
+            fetch(&repo)
+        });
+
+        if let Err(e) = result {
+            std::fs::remove_dir_all(&path)?;
+            return Err(git_err(e));
+        }
+
+        Ok(path)
+    }
+
+    pub fn list(&self) -> Result<Vec<MirrorInfo>, std::io::Error> {
+        let mut mirrors = vec![];
+        if self.dir.exists() {
+            find_mirrors(&self.dir, &mut mirrors)?;
+        }
+
+        mirrors.sort_by(|a, b| a.url.cmp(&b.url));
+        Ok(mirrors)
+    }
+
+    /// Removes all mirrors whose url is not in `used`, returning them
+    pub fn prune(&self, used: &[String]) -> Result<Vec<MirrorInfo>, std::io::Error> {
+        let unused: Vec<_> = self
+            .list()?
+            .into_iter()
+            .filter(|m| !used.contains(&m.url))
+            .collect();
+
+        for mirror in &unused {
+            std::fs::remove_dir_all(&mirror.path)?;
+        }
+
+        Ok(unused)
+    }
+}
+
+/// Fetches all branches and tags and points `HEAD` to the remote's default branch
+fn fetch(repo: &Repository) -> Result<(), git2::Error> {
+    let mut remote = repo.find_remote("origin")?;
+
+    remote.connect(Direction::Fetch)?;
+    let head = remote.default_branch()?;
+    remote.disconnect()?;
+
+    remote.fetch::<&str>(&[], None, None)?;
+    if let Some(head) = head.as_str() {
+        repo.set_head(head)?;
+    }
+
+    Ok(())
+}
+
+fn find_mirrors(dir: &Path, mirrors: &mut Vec<MirrorInfo>) -> Result<(), std::io::Error> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.extension().is_none_or(|e| e != "git") {
+            find_mirrors(&path, mirrors)?;
+            continue;
+        }
+
+        let Ok(repo) = Repository::open_bare(&path) else {
+            continue;
+        };
+        let url = repo
+            .find_remote("origin")
+            .ok()
+            .and_then(|r| r.url().map(str::to_string))
+            .unwrap_or_default();
+        let fetched_at = ["FETCH_HEAD", "HEAD"]
+            .iter()
+            .find_map(|f| path.join(f).metadata().and_then(|m| m.modified()).ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        mirrors.push(MirrorInfo {
+            url,
+            size: dir_size(&path)?,
+            fetched_at,
+            path,
+        });
+    }
+
+    Ok(())
+}
+
+/// Total size of all files in `path`, in bytes
+pub fn dir_size(path: &Path) -> Result<u64, std::io::Error> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{commit, source_repo};
+
+    #[test]
+    fn mirror_and_refresh() {
+        let tmp = tempfile::tempdir().unwrap();
+        let url = source_repo(&tmp.path().join("src"));
+        let cache = Cache::new(tmp.path().join("cache"), false);
+
+        let mirror = cache.mirror(&url).unwrap();
+        assert!(mirror.starts_with(cache.dir()));
+        assert_eq!(cache.list().unwrap()[0].url, url);
+
+        let source = Repository::open(tmp.path().join("src")).unwrap();
+        let new = commit(&source, "file", "new");
+        cache.mirror(&url).unwrap();
+
+        let mirror = Repository::open_bare(&mirror).unwrap();
+        assert_eq!(mirror.head().unwrap().target(), Some(new));
+    }
+
+    #[test]
+    fn offline() {
+        let tmp = tempfile::tempdir().unwrap();
+        let url = source_repo(&tmp.path().join("src"));
+        let offline = Cache::new(tmp.path().join("cache"), true);
+
+        assert!(matches!(
+            offline.mirror(&url),
+            Err(CacheError::NotCached(_))
+        ));
+
+        Cache::new(tmp.path().join("cache"), false)
+            .mirror(&url)
+            .unwrap();
+        assert!(offline.mirror(&url).is_ok());
+    }
+
+    #[test]
+    fn prune() {
+        let tmp = tempfile::tempdir().unwrap();
+        let a = source_repo(&tmp.path().join("a"));
+        let b = source_repo(&tmp.path().join("b"));
+        let cache = Cache::new(tmp.path().join("cache"), false);
+        cache.mirror(&a).unwrap();
+        cache.mirror(&b).unwrap();
+
+        let pruned = cache.prune(&[a]).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].url, b);
+        assert_eq!(cache.list().unwrap().len(), 1);
+    }
+}
@@ -1,34 +1,164 @@
 use std::path::PathBuf;
 
 use clap::{Args, Parser, Subcommand};
+use clap_complete::ArgValueCandidates;
+use run_that::{logs::Stage, manifest::ManifestFormat, output::OutputFormat};
+
+use crate::completions::{self, CompletionShell};
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
 pub(crate) struct GlobalArgs {
     #[clap(subcommand)]
     pub(crate) action: GlobalAction,
+    /// Output format, `json` and `yaml` follow the schema documented in `run_that::output`
+    /// [default: `format` from the config, or human]
+    #[clap(long, global = true, value_enum)]
+    pub(crate) format: Option<OutputFormat>,
+    /// Don't access the network, git packages are resolved from the cache only
+    #[clap(long, global = true)]
+    pub(crate) offline: bool,
+    /// Show what caused an error
+    #[clap(short, long, global = true)]
+    pub(crate) verbose: bool,
 }
 
 #[derive(Debug, Subcommand)]
 pub(crate) enum GlobalAction {
-    Check,
+    Check(CheckArguments),
     Install(InstallArguments),
-    Remove,
+    Remove(RemoveArguments),
+    Update(UpdateArguments),
+    Rollback(RollbackArguments),
+    Outdated,
+    Changelog(ChangelogArguments),
+    Run(RunArguments),
+    Use(UseArguments),
+    Start(RunArguments),
+    Stop(ServiceArguments),
+    Restart(ServiceArguments),
+    Status(StatusArguments),
     Show(ShowArguments),
     Info(InfoArgumnets),
+    Convert(ConvertArguments),
+    Export(ExportArguments),
+    Import(ImportArguments),
+    Link(LinkArguments),
+    Doctor,
+    Cache(CacheArguments),
+    Gc(GcArguments),
+    Logs(LogsArguments),
+    Completions(CompletionsArguments),
+    Config(ConfigArguments),
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct CheckArguments {
+    #[clap(short, long, conflicts_with = "path")]
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: Option<String>,
+    #[clap(short, long, conflicts_with = "name", required = false, default_value = ".")]
+    pub path: Option<PathBuf>,
 }
 
 #[derive(Debug, Args)]
 pub(crate) struct InstallArguments {
+    /// Git address like `user/name@v1.2.0`, `user/name@^1.2` or `user/name$branch`, or URL of a
+    /// .tar.gz, .tar.xz or .zip archive
     #[clap(required_unless_present = "path")]
     pub address: Option<String>,
+    /// Local directory or archive
     #[clap(short, long)]
     pub path: Option<PathBuf>,
+    /// Expected sha256 of the archive, rejected for other sources
+    #[clap(long)]
+    pub sha256: Option<String>,
+    /// Reinstall the package if it's already installed
+    #[clap(short, long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct RemoveArguments {
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+    /// Remove the package even if its uninstall commands fail
+    #[clap(short, long)]
+    pub force: bool,
+    /// Don't ask for confirmation, even if `confirm` is set in the config
+    #[clap(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct UpdateArguments {
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+    /// Rerun all init commands, even if nothing they depend on changed
+    #[clap(long)]
+    pub force_init: bool,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct RollbackArguments {
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+    /// Commit from the history shown by `info`, or how many revisions to go back [default: 1]
+    #[clap(long, value_name = "COMMIT|N")]
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ChangelogArguments {
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct RunArguments {
+    /// Value of a `${param:NAME}` variable used by the package
+    #[clap(short = 'P', long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, String)>,
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+    /// Arguments passed to the package
+    #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct UseArguments {
+    /// Installed version to use when the package is named without one, like `tool@v1`
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ServiceArguments {
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct StatusArguments {
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: Option<String>,
+}
+
+fn parse_param(param: &str) -> Result<(String, String), String> {
+    param
+        .split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected NAME=VALUE, got `{param}`"))
 }
 
 #[derive(Debug, Args)]
 pub(crate) struct InfoArgumnets {
+    /// Git address of a package to preview without installing it, like `user/name@tag`
+    #[clap(conflicts_with_all = ["name", "path"])]
+    pub address: Option<String>,
     #[clap(short, long, conflicts_with = "path")]
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
     pub name: Option<String>,
     #[clap(short, long, conflicts_with = "name", required = false, default_value = ".")]
     pub path: Option<PathBuf>,
@@ -40,4 +170,115 @@ pub(crate) struct ShowArguments {
     pub installed_packages: bool,
     #[clap(short = 'p', long, default_value = "false")]
     pub install_path: bool,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ConvertArguments {
+    /// Manifest file or package directory to convert
+    #[clap(default_value = ".")]
+    pub input: PathBuf,
+    /// Format to convert to, guessed from the output file name if not given
+    #[clap(short, long, required_unless_present = "output")]
+    pub to: Option<ManifestFormat>,
+    /// File to write to, prints to stdout if not given
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ExportArguments {
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+    /// Bundle to create, `<name>.tar.gz` if not given
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ImportArguments {
+    /// Bundle created by `export`
+    pub bundle: PathBuf,
+    /// Replace the package if it's already installed
+    #[clap(short, long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct CacheArguments {
+    #[clap(subcommand)]
+    pub action: CacheAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum CacheAction {
+    /// List mirrors of remotes
+    List,
+    /// Remove mirrors not used by any installed package
+    Prune,
+}
+
+/// Settings are read from `config.yml` in the data directory. Environment variables
+/// `RUN_THAT_DEFAULT_DOMAIN`, `RUN_THAT_FORMAT` and `RUN_THAT_CONFIRM` override them,
+/// command line flags override both.
+#[derive(Debug, Args)]
+pub(crate) struct ConfigArguments {
+    #[clap(subcommand)]
+    pub action: ConfigAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum ConfigAction {
+    /// Print the effective value of a setting
+    Get { key: String },
+    /// Change a setting in the config file, like `format json` or `shorthands.corp git.corp.com`
+    Set { key: String, value: String },
+    /// Remove a map entry or reset a setting to its default
+    Unset { key: String },
+    /// List all effective settings
+    List,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct LinkArguments {
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+    /// Name of the launcher, the package name if not given
+    #[clap(long = "as")]
+    pub as_name: Option<String>,
+    /// Overwrite conflicting launchers and shadow other programs
+    #[clap(short, long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct GcArguments {
+    /// Only report what would be reclaimed
+    #[clap(short = 'n', long)]
+    pub dry_run: bool,
+    /// Leave alone anything touched in this many hours
+    #[clap(long, default_value_t = 24)]
+    pub keep_recent: u64,
+    /// Don't ask for confirmation, even if `confirm` is set in the config
+    #[clap(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct LogsArguments {
+    #[clap(add = ArgValueCandidates::new(completions::installed_packages))]
+    pub name: String,
+    /// Print the latest log instead of listing them
+    #[clap(short, long)]
+    pub last: bool,
+    /// Print the latest log and keep printing what's appended to it
+    #[clap(short, long)]
+    pub follow: bool,
+    /// Only logs of this stage
+    #[clap(short, long, value_enum)]
+    pub stage: Option<Stage>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct CompletionsArguments {
+    pub shell: CompletionShell,
+}
@@ -0,0 +1,117 @@
+use std::io::Write;
+
+use clap::{CommandFactory, ValueEnum};
+use clap_complete::{
+    env::{Bash, EnvCompleter, Fish, Zsh},
+    CompleteEnv, CompletionCandidate,
+};
+use run_that::manager::Manager;
+
+use crate::cli::GlobalArgs;
+
+/// Environment variable the shell sets when asking us for completions
+const COMPLETE_VAR: &str = "COMPLETE";
+const BIN_NAME: &str = "run-that";
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Answers completion requests from the shell, exiting the process if there was one
+pub(crate) fn handle_request() {
+    CompleteEnv::with_factory(GlobalArgs::command)
+        .var(COMPLETE_VAR)
+        .bin(BIN_NAME)
+        .complete();
+}
+
+/// Writes the script that registers dynamic completions in `shell`
+pub(crate) fn write_registration(
+    shell: CompletionShell,
+    buf: &mut dyn Write,
+) -> std::io::Result<()> {
+    let completer: &dyn EnvCompleter = match shell {
+        CompletionShell::Bash => &Bash,
+        CompletionShell::Zsh => &Zsh,
+        CompletionShell::Fish => &Fish,
+    };
+
+    completer.write_registration(COMPLETE_VAR, BIN_NAME, BIN_NAME, BIN_NAME, buf)
+}
+
+/// Installed packages, both by full id and by short name when it's unique
+pub(crate) fn installed_packages() -> Vec<CompletionCandidate> {
+    match Manager::from_env() {
+        Ok(manager) => package_candidates(&manager),
+        Err(_) => vec![],
+    }
+}
+
+/// Packages installed by `manager`, read the same way `show -i` reads them
+fn package_candidates(manager: &Manager) -> Vec<CompletionCandidate> {
+    let Ok(installed) = manager.list() else {
+        return vec![];
+    };
+
+    let short_name = |id: &str| id.rsplit('/').next().unwrap_or(id).to_string();
+    let mut candidates = vec![];
+    for package in &installed {
+        let help = package
+            .package
+            .as_ref()
+            .map(|p| p.description.clone().into());
+        candidates.push(CompletionCandidate::new(&package.id).help(help.clone()));
+
+        let name = short_name(&package.id);
+        if installed
+            .iter()
+            .filter(|p| short_name(&p.id) == name)
+            .count()
+            == 1
+        {
+            candidates.push(CompletionCandidate::new(name).help(help));
+        }
+    }
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installed_ids_and_short_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let manager = Manager::new(tmp.path()).unwrap();
+        assert!(package_candidates(&manager).is_empty());
+
+        // Checkouts as `install` leaves them in the data directory
+        for id in ["a/game", "a/tool", "b/tool"] {
+            let path = tmp.path().join("repos").join(id);
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::write(
+                path.join("run.yml"),
+                format!(
+                    "name: {id}\ndescription: {id} for tests\nauthors: []\n\
+                     run:\n  default:\n    program: tool\n"
+                ),
+            )
+            .unwrap();
+        }
+
+        let candidates = package_candidates(&manager);
+        let values: Vec<_> = candidates
+            .iter()
+            .map(|c| c.get_value().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(values, ["a/game", "game", "a/tool", "b/tool"]);
+        assert_eq!(
+            candidates[0].get_help().unwrap().to_string(),
+            "a/game for tests"
+        );
+    }
+}
@@ -0,0 +1,333 @@
+//! User configuration, stored in `MAIN_PATH/config.yml`.
+//!
+//! Every setting is resolved in this order, the first one set wins:
+//!
+//! 1. command line flags, like `--format`
+//! 2. environment variables `RUN_THAT_DEFAULT_DOMAIN`, `RUN_THAT_FORMAT` and `RUN_THAT_CONFIRM`
+//! 3. `config.yml`, edited by hand or with `run-that config set`
+//! 4. built-in defaults
+//!
+//! ```yaml
+//! default_domain: github.com
+//! shorthands:               # `gh:user/repo` is `github.com:user/repo`
+//!   gh: github.com
+//!   gl: gitlab.com
+//!   cb: codeberg.org
+//! url_rewrites:             # like git's `insteadOf`, the longest matching prefix is replaced
+//!   https://github.com/: https://mirror.example.com/github/
+//! confirm: false            # ask before `remove` and `gc`
+//! format: human
+//! ```
+
+use std::{collections::BTreeMap, path::Path};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::output::OutputFormat;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("could not access config file")]
+    Io(#[from] std::io::Error),
+    #[error("config file is invalid")]
+    Parse(#[from] serde_yaml::Error),
+    #[error("unknown config key `{0}`")]
+    UnknownKey(String),
+    #[error("config key `{0}` is not set")]
+    NotSet(String),
+    #[error("invalid value `{value}` of `{key}`, expected {expected}")]
+    InvalidValue {
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub default_domain: String, // Domain of git addresses without one
+    pub shorthands: BTreeMap<String, String>, // Address prefixes and the domains they stand for
+    pub url_rewrites: BTreeMap<String, String>, // URL prefixes replaced before fetching
+    pub confirm: bool,          // Ask before removing packages or reclaiming space
+    pub format: OutputFormat,   // Output format used without `--format`
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_domain: "github.com".to_string(),
+            shorthands: [
+                ("gh", "github.com"),
+                ("gl", "gitlab.com"),
+                ("cb", "codeberg.org"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            url_rewrites: BTreeMap::new(),
+            confirm: false,
+            format: OutputFormat::Human,
+        }
+    }
+}
+
+/// Environment variables overriding the config file and the keys they set
+const ENV_VARS: [(&str, &str); 3] = [
+    ("RUN_THAT_DEFAULT_DOMAIN", "default_domain"),
+    ("RUN_THAT_FORMAT", "format"),
+    ("RUN_THAT_CONFIRM", "confirm"),
+];
+
+impl Config {
+    /// Reads the config file, a missing one means the defaults
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let file = std::fs::File::open(path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+
+    /// Reads the config file, or the defaults with the reason it couldn't be read.
+    ///
+    /// `config` commands use this, so they work to repair an invalid file.
+    pub fn load_or_default(path: &Path) -> (Self, Option<ConfigError>) {
+        match Self::load(path) {
+            Ok(config) => (config, None),
+            Err(e) => (Self::default(), Some(e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Applies the environment variables on top of the file
+    pub fn with_env(mut self) -> Result<Self, ConfigError> {
+        for (var, key) in ENV_VARS {
+            if let Some(value) = std::env::var(var).ok().filter(|v| !v.is_empty()) {
+                self.set(key, &value)?;
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub fn get(&self, key: &str) -> Result<String, ConfigError> {
+        let value = match split_key(key)? {
+            (Key::DefaultDomain, _) => Some(self.default_domain.clone()),
+            (Key::Confirm, _) => Some(self.confirm.to_string()),
+            (Key::Format, _) => Some(format_name(self.format)),
+            (Key::Shorthand, name) => self.shorthands.get(name).cloned(),
+            (Key::UrlRewrite, prefix) => self.url_rewrites.get(prefix).cloned(),
+        };
+        value.ok_or_else(|| ConfigError::NotSet(key.to_string()))
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let invalid = |expected| ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+            expected,
+        };
+
+        match split_key(key)? {
+            (Key::DefaultDomain, _) if value.is_empty() => return Err(invalid("a domain")),
+            (Key::DefaultDomain, _) => self.default_domain = value.to_string(),
+            (Key::Confirm, _) => self.confirm = value.parse().map_err(|_| invalid("a boolean"))?,
+            (Key::Format, _) => {
+                self.format = OutputFormat::from_str(value, true)
+                    .map_err(|_| invalid("human, json or yaml"))?
+            }
+            (Key::Shorthand, name) => {
+                self.shorthands.insert(name.to_string(), value.to_string());
+            }
+            (Key::UrlRewrite, prefix) => {
+                self.url_rewrites
+                    .insert(prefix.to_string(), value.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes an entry of a map, or resets a plain setting to its default
+    pub fn unset(&mut self, key: &str) -> Result<(), ConfigError> {
+        let default = Self::default();
+        let removed = match split_key(key)? {
+            (Key::DefaultDomain, _) => {
+                self.default_domain = default.default_domain;
+                true
+            }
+            (Key::Confirm, _) => {
+                self.confirm = default.confirm;
+                true
+            }
+            (Key::Format, _) => {
+                self.format = default.format;
+                true
+            }
+            (Key::Shorthand, name) => self.shorthands.remove(name).is_some(),
+            (Key::UrlRewrite, prefix) => self.url_rewrites.remove(prefix).is_some(),
+        };
+
+        if !removed {
+            return Err(ConfigError::NotSet(key.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// All settings as `key = value` pairs, map entries are `map.key`
+    pub fn list(&self) -> Vec<(String, String)> {
+        let mut list = vec![
+            ("default_domain".to_string(), self.default_domain.clone()),
+            ("confirm".to_string(), self.confirm.to_string()),
+            ("format".to_string(), format_name(self.format)),
+        ];
+        for (name, domain) in &self.shorthands {
+            list.push((format!("shorthands.{name}"), domain.clone()));
+        }
+        for (prefix, replacement) in &self.url_rewrites {
+            list.push((format!("url_rewrites.{prefix}"), replacement.clone()));
+        }
+
+        list
+    }
+
+    /// Replaces a shorthand prefix of a git address with its domain, `gh:user/repo` to `github.com:user/repo`
+    pub fn expand_shorthand(&self, address: &str) -> String {
+        match address.split_once(':') {
+            Some((prefix, rest)) => match self.shorthands.get(prefix) {
+                Some(domain) => format!("{domain}:{rest}"),
+                None => address.to_string(),
+            },
+            None => address.to_string(),
+        }
+    }
+
+    /// Applies the longest matching URL rewrite
+    pub fn rewrite_url(&self, url: &str) -> String {
+        self.url_rewrites
+            .iter()
+            .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, replacement)| format!("{replacement}{}", &url[prefix.len()..]))
+            .unwrap_or_else(|| url.to_string())
+    }
+}
+
+enum Key {
+    DefaultDomain,
+    Confirm,
+    Format,
+    Shorthand,
+    UrlRewrite,
+}
+
+fn split_key(key: &str) -> Result<(Key, &str), ConfigError> {
+    let parsed = match key.split_once('.') {
+        None => match key {
+            "default_domain" => Some(Key::DefaultDomain),
+            "confirm" => Some(Key::Confirm),
+            "format" => Some(Key::Format),
+            _ => None,
+        }
+        .map(|k| (k, "")),
+        Some(("shorthands", name)) if !name.is_empty() => Some((Key::Shorthand, name)),
+        Some(("url_rewrites", prefix)) if !prefix.is_empty() => Some((Key::UrlRewrite, prefix)),
+        Some(_) => None,
+    };
+    parsed.ok_or_else(|| ConfigError::UnknownKey(key.to_string()))
+}
+
+fn format_name(format: OutputFormat) -> String {
+    format
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let mut config = Config::default();
+        config.set("format", "JSON").unwrap();
+        config.set("shorthands.corp", "git.corp.com").unwrap();
+        config
+            .set("url_rewrites.https://github.com/", "https://mirror/gh/")
+            .unwrap();
+
+        assert_eq!(config.get("format").unwrap(), "json");
+        assert_eq!(config.get("shorthands.corp").unwrap(), "git.corp.com");
+        assert!(matches!(
+            config.set("confirm", "maybe"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.get("colors"),
+            Err(ConfigError::UnknownKey(_))
+        ));
+        assert!(matches!(
+            config.get("shorthands.x"),
+            Err(ConfigError::NotSet(_))
+        ));
+
+        config.unset("shorthands.gh").unwrap();
+        config.unset("format").unwrap();
+        assert_eq!(config.format, OutputFormat::Human);
+        assert!(!config.list().iter().any(|(k, _)| k == "shorthands.gh"));
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("config.yml");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+
+        std::fs::write(&path, "format: xml\n").unwrap();
+        let (config, error) = Config::load_or_default(&path);
+        assert_eq!(config, Config::default());
+        assert!(matches!(error, Some(ConfigError::Parse(_))));
+    }
+
+    #[test]
+    fn addresses() {
+        let mut config = Config::default();
+        config
+            .set("url_rewrites.https://github.com/", "https://mirror/gh/")
+            .unwrap();
+        config
+            .set("url_rewrites.https://github.com/corp/", "ssh://git@corp/")
+            .unwrap();
+
+        assert_eq!(
+            config.expand_shorthand("gl:user/repo"),
+            "gitlab.com:user/repo"
+        );
+        assert_eq!(config.expand_shorthand("user/repo"), "user/repo");
+        assert_eq!(
+            config.rewrite_url("https://github.com/user/repo"),
+            "https://mirror/gh/user/repo"
+        );
+        assert_eq!(
+            config.rewrite_url("https://github.com/corp/repo"),
+            "ssh://git@corp/repo"
+        );
+        assert_eq!(
+            config.rewrite_url("https://gitlab.com/user/repo"),
+            "https://gitlab.com/user/repo"
+        );
+    }
+}
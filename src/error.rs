@@ -0,0 +1,288 @@
+//! Errors of the whole crate and the exit codes the CLI reports them with.
+//!
+//! | code | kind              | meaning                                                      |
+//! |------|-------------------|--------------------------------------------------------------|
+//! | 0    |                   | success                                                      |
+//! | 1    | `other`           | anything not listed below                                    |
+//! | 2    |                   | invalid command line arguments                               |
+//! | 3    | `not_installed`   | not installed, or the name is ambiguous                      |
+//! | 4    | `invalid_manifest`| the manifest is missing, can't be parsed or fails the lint   |
+//! | 5    | `check_failed`    | checks of the package failed                                 |
+//! | 6    | `run_failed`      | an init or hook command failed, or the program didn't start  |
+//! | 7    | `network`         | fetching a package or a git remote failed                    |
+//!
+//! `run` exits with the code of the program instead, when the program itself fails.
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    archive::ArchiveError,
+    cache::CacheError,
+    config::ConfigError,
+    manager::{ManagerError, PackageInfoError},
+    manifest::ManifestError,
+    package_index::{
+        errors::{GitCloneError, InvalidGitAddress},
+        split_version,
+    },
+};
+
+/// What went wrong, see the module docs for the exit codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Other,
+    NotInstalled,
+    InvalidManifest,
+    CheckFailed,
+    RunFailed,
+    Network,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::NotInstalled => 3,
+            ErrorKind::InvalidManifest => 4,
+            ErrorKind::CheckFailed => 5,
+            ErrorKind::RunFailed => 6,
+            ErrorKind::Network => 7,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Manager(#[from] ManagerError),
+    #[error(transparent)]
+    PackageInfo(#[from] PackageInfoError),
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+    #[error(transparent)]
+    InvalidAddress(#[from] InvalidGitAddress),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("could not access {0:?}")]
+    Io(std::path::PathBuf, #[source] std::io::Error),
+    #[error("could not use the terminal")]
+    Terminal(#[from] std::io::Error), // Reading answers from stdin or writing to stdout
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Manager(e) => manager_kind(e),
+            Error::PackageInfo(_) | Error::Manifest(_) => ErrorKind::InvalidManifest,
+            Error::InvalidAddress(_) | Error::Config(_) | Error::Io(..) | Error::Terminal(_) => {
+                ErrorKind::Other
+            }
+        }
+    }
+
+    /// What the user can do about it
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            Error::Manager(e) => manager_hint(e),
+            Error::PackageInfo(e) => package_info_hint(e),
+            Error::InvalidAddress(e) => Some(address_hint(e)),
+            Error::Config(e) => config_hint(e),
+            _ => None,
+        }
+    }
+
+    /// Messages of the errors that caused this one, the innermost last
+    pub fn causes(&self) -> Vec<String> {
+        let mut causes = vec![];
+        let mut source = std::error::Error::source(self);
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+
+        // Transparent variants repeat the message of the wrapped error
+        if causes.first().is_some_and(|c| *c == self.to_string()) {
+            causes.remove(0);
+        }
+        causes
+    }
+}
+
+fn manager_kind(error: &ManagerError) -> ErrorKind {
+    match error {
+        ManagerError::NotInstalled(..) | ManagerError::Ambiguous(..) => ErrorKind::NotInstalled,
+        ManagerError::PackageInfo(_) | ManagerError::Variable(_) => ErrorKind::InvalidManifest,
+        ManagerError::ChecksFailed(_) => ErrorKind::CheckFailed,
+        ManagerError::InitFailed(_)
+        | ManagerError::HookFailed(..)
+        | ManagerError::RunFailed(..) => ErrorKind::RunFailed,
+        ManagerError::Fetch(_, e) if is_network(e) => ErrorKind::Network,
+        _ => ErrorKind::Other,
+    }
+}
+
+fn is_network(error: &GitCloneError) -> bool {
+    match error {
+        GitCloneError::CannotClone(e) | GitCloneError::Cache(CacheError::Git(_, e)) => {
+            // Other classes are local, like a missing ref or a broken checkout
+            matches!(
+                e.class(),
+                git2::ErrorClass::Net | git2::ErrorClass::Http | git2::ErrorClass::Ssh
+            )
+        }
+        GitCloneError::Archive(ArchiveError::Download(..)) => true,
+        _ => false,
+    }
+}
+
+fn manager_hint(error: &ManagerError) -> Option<String> {
+    match error {
+        ManagerError::NotInstalled(_, Some(suggestion)) => {
+            Some(format!("did you mean `{suggestion}`?"))
+        }
+        ManagerError::NotInstalled(name, None) => Some(format!(
+            "install it with `run-that install {name}`, installed packages are listed by `run-that show -i`"
+        )),
+        ManagerError::Ambiguous(_, ids) => {
+            let list = ids
+                .iter()
+                .map(|id| format!("`{id}`"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            // Several versions of one package, none of them active
+            let package = ids.first().map(|id| split_version(id).0);
+            if ids.iter().all(|id| Some(split_version(id).0) == package) {
+                Some(format!(
+                    "name a version, one of {list}, or choose the default one with `run-that use`"
+                ))
+            } else {
+                Some(format!("use the full id, one of {list}"))
+            }
+        }
+        ManagerError::InvalidAddress(e) => Some(address_hint(e)),
+        ManagerError::PackageInfo(e) => package_info_hint(e),
+        ManagerError::ChecksFailed(report) => Some(format!(
+            "see the output with `run-that logs {} --last --stage check`",
+            report.package
+        )),
+        ManagerError::NoRevision(id, _) => Some(format!(
+            "see the revisions with `run-that info -n {id}`"
+        )),
+        ManagerError::InitFailed(_) => {
+            Some("see the output with `run-that logs <package> --last --stage init`".to_string())
+        }
+        ManagerError::Fetch(_, GitCloneError::Cache(CacheError::NotCached(_))) => {
+            Some("run it again without `--offline`".to_string())
+        }
+        ManagerError::Fetch(_, GitCloneError::NoMatchingTag(_)) => Some(
+            "version ranges only match release tags like `v1.2.3`, see them with `git ls-remote --tags`"
+                .to_string(),
+        ),
+        ManagerError::Config(e) => config_hint(e),
+        ManagerError::Fetch(_, e) if is_network(e) => {
+            Some("check the address and your connection, cached packages work with `--offline`".to_string())
+        }
+        _ => None,
+    }
+}
+
+fn config_hint(error: &ConfigError) -> Option<String> {
+    match error {
+        ConfigError::Parse(_) => Some(
+            "fix the file by hand, or replace it with `run-that config set` and `config unset`"
+                .to_string(),
+        ),
+        _ => None,
+    }
+}
+
+fn package_info_hint(error: &PackageInfoError) -> Option<String> {
+    match error {
+        PackageInfoError::NoManifest(_) => {
+            Some("a package needs a `run.yml`, `run.toml` or `run.json`".to_string())
+        }
+        _ => None,
+    }
+}
+
+fn address_hint(error: &InvalidGitAddress) -> String {
+    match &error.suggestion {
+        Some(suggestion) => format!("did you mean `{suggestion}`?"),
+        None => "git addresses look like `user/name`, `domain:user/name` or `gh:user/name@tag`"
+            .to_string(),
+    }
+}
+
+/// Candidate closest to `name` by edit distance, if it's close enough to be a typo
+pub(crate) fn closest<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|c| (distance(name, c), c))
+        .filter(|(d, c)| *d <= (c.len() / 3).max(1))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance of two strings
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut row: Vec<_> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggestions() {
+        let ids = ["ur-fault/run-that", "ur-fault/lil-game", "user/tool"];
+        assert_eq!(closest("ur-fault/run-tht", ids), Some("ur-fault/run-that"));
+        assert_eq!(closest("tol", ["tool", "game"]), Some("tool"));
+        assert_eq!(closest("something", ids), None);
+        assert_eq!(distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn kinds() {
+        let not_installed: Error =
+            ManagerError::NotInstalled("tol".into(), Some("tool".into())).into();
+        assert_eq!(not_installed.kind().exit_code(), 3);
+        assert_eq!(not_installed.hint().unwrap(), "did you mean `tool`?");
+
+        let offline: Error =
+            ManagerError::fetch("u/r")(CacheError::NotCached("https://x/y".into())).into();
+        assert_eq!(offline.kind(), ErrorKind::Other);
+        assert!(offline.hint().unwrap().contains("--offline"));
+        assert_eq!(offline.to_string(), "could not get `u/r`");
+
+        let git = |class| -> Error {
+            let error = git2::Error::new(git2::ErrorCode::GenericError, class, "failed");
+            ManagerError::fetch("u/r")(error).into()
+        };
+        assert_eq!(git(git2::ErrorClass::Net).kind(), ErrorKind::Network);
+        assert_eq!(git(git2::ErrorClass::Ssh).kind(), ErrorKind::Network);
+        assert_eq!(git(git2::ErrorClass::Reference).kind(), ErrorKind::Other);
+
+        let io: Error = ManagerError::io("/data/repos")(std::io::ErrorKind::NotFound.into()).into();
+        assert_eq!(io.to_string(), "could not access \"/data/repos\"");
+
+        let manifest: Error = PackageInfoError::NoManifest("/tmp".into()).into();
+        assert_eq!(manifest.kind(), ErrorKind::InvalidManifest);
+        assert!(manifest.causes().is_empty());
+    }
+}
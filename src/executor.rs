@@ -0,0 +1,425 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::Path,
+    process::{Child, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    logs::{self, Log},
+    manager::StepResult,
+    package::Command,
+};
+
+/// Where output of a command is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    Null,
+    Stdout,
+    Stderr,
+}
+
+impl Sink {
+    fn stdio(self) -> Stdio {
+        match self {
+            Sink::Null => Stdio::null(),
+            Sink::Stdout => Stdio::inherit(),
+            Sink::Stderr => std::io::stderr().into(),
+        }
+    }
+
+    pub fn writer(self) -> Box<dyn Write + Send> {
+        match self {
+            Sink::Null => Box::new(std::io::sink()),
+            Sink::Stdout => Box::new(std::io::stdout()),
+            Sink::Stderr => Box::new(std::io::stderr()),
+        }
+    }
+}
+
+/// A single attempt of a command, handed to an [`Executor`]
+#[derive(Debug, Clone, Copy)]
+pub struct Step<'a> {
+    pub command: &'a Command, // Variables are already expanded
+    pub dir: &'a Path,        // Package directory the command runs in
+    pub stdout: Sink,
+    pub stderr: Sink,
+    pub log: Option<&'a Log>, // Output is copied into it as well
+    pub detached: bool,       // Started in its own process group and not waited for
+}
+
+/// Runs commands of packages, every `init`, `checks`, `run` and hook step goes through it.
+///
+/// Retries, variables and the log header are handled by the manager, an executor only
+/// makes a single attempt. Services are detached steps, their result has the `pid` of the
+/// started process.
+pub trait Executor: Send + Sync {
+    fn execute(&self, step: &Step) -> StepResult;
+}
+
+impl<T: Executor + ?Sized> Executor for Arc<T> {
+    fn execute(&self, step: &Step) -> StepResult {
+        (**self).execute(step)
+    }
+}
+
+impl<T: Executor + ?Sized> Executor for Box<T> {
+    fn execute(&self, step: &Step) -> StepResult {
+        (**self).execute(step)
+    }
+}
+
+/// Runs commands as local processes, killing them after their timeout
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Local;
+
+impl Executor for Local {
+    fn execute(&self, step: &Step) -> StepResult {
+        let Step {
+            command,
+            stdout,
+            stderr,
+            log,
+            ..
+        } = *step;
+        if step.detached {
+            return detach(step);
+        }
+
+        let mut process = command.to_process(step.dir);
+        process.stdin(Stdio::inherit());
+        // Commands with a timeout get their own process group, so what they started dies with
+        // them. Others stay in ours, where the terminal and Ctrl+C can reach them.
+        #[cfg(unix)]
+        if command.timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+            process.process_group(0);
+        }
+        match log {
+            Some(_) => process.stdout(Stdio::piped()).stderr(Stdio::piped()),
+            None => process.stdout(stdout.stdio()).stderr(stderr.stdio()),
+        };
+
+        let mut child = match process.spawn() {
+            Ok(child) => child,
+            Err(e) => return StepResult::failed(command, e),
+        };
+
+        let mut copiers = vec![];
+        if let Some(log) = log {
+            if let Some(out) = child.stdout.take() {
+                let log = log.clone();
+                copiers.push(std::thread::spawn(move || {
+                    logs::tee(out, stdout.writer(), &log)
+                }));
+            }
+            if let Some(err) = child.stderr.take() {
+                let log = log.clone();
+                copiers.push(std::thread::spawn(move || {
+                    logs::tee(err, stderr.writer(), &log)
+                }));
+            }
+        }
+
+        let result = wait(command, &mut child);
+        for copier in copiers {
+            let _ = copier.join();
+        }
+        result
+    }
+}
+
+/// Starts the command in its own process group with output appended to the log, if any.
+///
+/// The child is not waited for, it's reparented once we exit.
+#[cfg(unix)]
+fn detach(step: &Step) -> StepResult {
+    use std::os::unix::process::CommandExt;
+
+    let output = |sink: Sink| -> std::io::Result<Stdio> {
+        match step.log {
+            Some(log) => {
+                if let Some(dir) = log.path().parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                Ok(std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(log.path())?
+                    .into())
+            }
+            None => Ok(sink.stdio()),
+        }
+    };
+
+    let mut process = step.command.to_process(step.dir);
+    let child = output(step.stdout)
+        .and_then(|stdout| Ok((stdout, output(step.stderr)?)))
+        .and_then(|(stdout, stderr)| {
+            process
+                .stdin(Stdio::null())
+                .stdout(stdout)
+                .stderr(stderr)
+                .process_group(0)
+                .spawn()
+        });
+    match child {
+        Ok(child) => StepResult {
+            success: true,
+            pid: Some(child.id()),
+            ..StepResult::exited(step.command)
+        },
+        Err(e) => StepResult::failed(step.command, e),
+    }
+}
+
+#[cfg(not(unix))]
+fn detach(step: &Step) -> StepResult {
+    StepResult::failed(
+        step.command,
+        "background services are not supported on this platform",
+    )
+}
+
+fn wait(command: &Command, child: &mut Child) -> StepResult {
+    let status = match command.timeout {
+        Some(timeout) => wait_timeout(child, Duration::from_secs(timeout)),
+        None => child.wait().map(Some),
+    };
+
+    match status {
+        Ok(Some(status)) => StepResult {
+            success: status.code().is_some_and(|c| command.is_success(c)),
+            code: status.code(),
+            ..StepResult::exited(command)
+        },
+        Ok(None) => StepResult {
+            timed_out: true,
+            ..StepResult::failed(command, "timed out")
+        },
+        Err(e) => StepResult::failed(command, e),
+    }
+}
+
+/// Waits for the child to exit, killing its group after `timeout`, `None` if it was killed
+fn wait_timeout(child: &mut Child, timeout: Duration) -> std::io::Result<Option<ExitStatus>> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout {
+            kill_group(child)?;
+            child.wait()?;
+            return Ok(None);
+        }
+
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[cfg(unix)]
+fn kill_group(child: &mut Child) -> std::io::Result<()> {
+    // SAFETY: `kill` has no memory safety requirements
+    match unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_group(child: &mut Child) -> std::io::Result<()> {
+    child.kill()
+}
+
+/// Prints commands instead of running them, every one of them succeeds
+#[derive(Debug, Default)]
+pub struct DryRun {
+    commands: Mutex<Vec<Command>>,
+}
+
+impl DryRun {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Commands that would have run, in order
+    pub fn commands(&self) -> Vec<Command> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+impl Executor for DryRun {
+    fn execute(&self, step: &Step) -> StepResult {
+        let _ = writeln!(step.stdout.writer(), "would run: {}", step.command);
+        self.commands.lock().unwrap().push(step.command.clone());
+        StepResult {
+            success: true,
+            code: Some(0),
+            ..StepResult::exited(step.command)
+        }
+    }
+}
+
+/// Executor for tests, records commands and exits with preset codes, 0 by default
+#[derive(Debug, Default)]
+pub struct Mock {
+    codes: BTreeMap<String, i32>,
+    calls: Mutex<Vec<Command>>,
+}
+
+impl Mock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes every run of `program` exit with `code`
+    pub fn exit_with(mut self, program: &str, code: i32) -> Self {
+        self.codes.insert(program.to_string(), code);
+        self
+    }
+
+    /// Commands executed so far, in order
+    pub fn calls(&self) -> Vec<Command> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl Executor for Mock {
+    fn execute(&self, step: &Step) -> StepResult {
+        self.calls.lock().unwrap().push(step.command.clone());
+        let code = self.codes.get(&step.command.program).copied().unwrap_or(0);
+        StepResult {
+            success: step.command.is_success(code),
+            code: Some(code),
+            ..StepResult::exited(step.command)
+        }
+    }
+}
+
+/// Runs every command under a prefix like `nice -n 10`, `firejail` or `systemd-run --user --scope`
+pub struct Prefixed<E> {
+    prefix: Vec<String>,
+    inner: E,
+}
+
+impl<E: Executor> Prefixed<E> {
+    /// The first item of `prefix` is the program to run, an empty prefix changes nothing
+    pub fn new(prefix: impl IntoIterator<Item = impl Into<String>>, inner: E) -> Self {
+        Self {
+            prefix: prefix.into_iter().map(Into::into).collect(),
+            inner,
+        }
+    }
+
+    fn wrap(&self, command: &Command) -> Command {
+        let Some((program, args)) = self.prefix.split_first() else {
+            return command.clone();
+        };
+
+        let mut wrapped = command.clone();
+        wrapped.program = program.clone();
+        wrapped.args = args
+            .iter()
+            .chain([&command.program])
+            .chain(&command.args)
+            .cloned()
+            .collect();
+        wrapped
+    }
+}
+
+impl<E: Executor> Executor for Prefixed<E> {
+    fn execute(&self, step: &Step) -> StepResult {
+        let wrapped = self.wrap(step.command);
+        let result = self.inner.execute(&Step {
+            command: &wrapped,
+            ..*step
+        });
+
+        // Callers see the command they asked for
+        StepResult {
+            command: step.command.clone(),
+            ..result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(command: &Command) -> Step<'_> {
+        Step {
+            command,
+            dir: Path::new("."),
+            stdout: Sink::Null,
+            stderr: Sink::Null,
+            log: None,
+            detached: false,
+        }
+    }
+
+    #[test]
+    fn mock_and_prefix() {
+        let make = Command {
+            program: "make".to_string(),
+            args: vec!["all".to_string()],
+            ..Default::default()
+        };
+
+        let mock = Arc::new(Mock::new().exit_with("nice", 2));
+        let nice = Prefixed::new(["nice", "-n", "10"], mock.clone());
+        let result = nice.execute(&step(&make));
+        assert!(!result.success);
+        assert_eq!(result.code, Some(2));
+        assert_eq!(result.command, make);
+
+        let calls = mock.calls();
+        assert_eq!(calls[0].program, "nice");
+        assert_eq!(calls[0].args, ["-n", "10", "make", "all"]);
+        assert!(Mock::new().execute(&step(&make)).success);
+    }
+
+    #[test]
+    fn dry_run() {
+        let dry = DryRun::new();
+        let command = Command {
+            program: "definitely-not-installed".to_string(),
+            ..Default::default()
+        };
+
+        assert!(dry.execute(&step(&command)).success);
+        assert_eq!(dry.commands(), [command]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_kills_group() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = Log::new(tmp.path(), "u/r", logs::Stage::Init);
+        // The background sleep holds the output pipes open unless it's killed too
+        let command = Command {
+            program: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "sleep 30 & echo started; wait".to_string(),
+            ],
+            timeout: Some(1),
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let result = Local.execute(&Step {
+            log: Some(&log),
+            ..step(&command)
+        });
+        assert!(result.timed_out);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(std::fs::read_to_string(log.path())
+            .unwrap()
+            .contains("started"));
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::package::Command;
+
+/// Fingerprint of an init step, `None` if the step can't be skipped.
+///
+/// Steps declaring `inputs` depend on the content of the matching files, other steps
+/// on the checked out commit, so they always run for packages that aren't git repos.
+pub fn fingerprint(
+    command: &Command,
+    dir: &Path,
+    commit: Option<&str>,
+) -> std::io::Result<Option<String>> {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(command).map_err(std::io::Error::other)?);
+
+    if command.inputs.is_empty() {
+        match commit {
+            Some(commit) => hasher.update(commit),
+            None => return Ok(None),
+        }
+    } else {
+        for input in find_inputs(dir, &command.inputs)? {
+            hasher.update(input.as_bytes());
+            hasher.update([0]);
+            std::io::copy(&mut std::fs::File::open(dir.join(&input))?, &mut hasher)?;
+        }
+    }
+
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+/// Fingerprint of all files in `dir` but `.git`, tells whether a copied package changed
+pub fn tree(dir: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    for file in find_inputs(dir, &["**".to_string()])? {
+        hasher.update(file.as_bytes());
+        hasher.update([0]);
+        std::io::copy(&mut std::fs::File::open(dir.join(&file))?, &mut hasher)?;
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Whether all declared outputs of the step exist, a step is rerun if any is missing
+pub fn outputs_exist(command: &Command, dir: &Path) -> bool {
+    command
+        .outputs
+        .iter()
+        .all(|output| dir.join(output).exists())
+}
+
+/// Files in `dir` matching any of the globs, as sorted relative paths.
+///
+/// A glob matching a directory selects everything inside it. `.git` is never searched.
+fn find_inputs(dir: &Path, globs: &[String]) -> std::io::Result<Vec<String>> {
+    let mut inputs = vec![];
+    walk(dir, "", false, globs, &mut inputs)?;
+    inputs.sort();
+    Ok(inputs)
+}
+
+fn walk(
+    dir: &Path,
+    prefix: &str,
+    selected: bool,
+    globs: &[String],
+    inputs: &mut Vec<String>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == ".git" {
+            continue;
+        }
+
+        let path = format!("{prefix}{name}");
+        let selected = selected || globs.iter().any(|glob| glob_match(glob, &path));
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), &format!("{path}/"), selected, globs, inputs)?;
+        } else if selected {
+            inputs.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Matches a `/` separated path, `*` and `?` stay within one component, `**` spans any number
+fn glob_match(glob: &str, path: &str) -> bool {
+    let glob: Vec<_> = glob.trim_start_matches("./").split('/').collect();
+    let path: Vec<_> = path.split('/').collect();
+    match_components(&glob, &path)
+}
+
+fn match_components(glob: &[&str], path: &[&str]) -> bool {
+    match glob.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| match_components(rest, &path[i..])),
+        Some((first, rest)) => path.split_first().is_some_and(|(name, path)| {
+            match_name(first.as_bytes(), name.as_bytes()) && match_components(rest, path)
+        }),
+    }
+}
+
+fn match_name(glob: &[u8], name: &[u8]) -> bool {
+    match glob.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| match_name(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && match_name(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_name(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("src/*.rs", "src/main.rs"));
+        assert!(!glob_match("src/*.rs", "src/bin/main.rs"));
+        assert!(glob_match("src/**/*.rs", "src/main.rs"));
+        assert!(glob_match("src/**/*.rs", "src/bin/main.rs"));
+        assert!(glob_match("**/Cargo.toml", "Cargo.toml"));
+        assert!(glob_match("./build.?s", "build.rs"));
+        assert!(!glob_match("Cargo.*", "Cargo"));
+    }
+
+    #[test]
+    fn fingerprints() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("src/bin")).unwrap();
+        std::fs::write(tmp.path().join("src/bin/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(tmp.path().join("README.md"), "docs").unwrap();
+
+        let build = Command {
+            program: "cargo".to_string(),
+            inputs: vec!["src".to_string()],
+            outputs: vec!["target/release/tool".to_string()],
+            ..Default::default()
+        };
+        let plain = Command {
+            program: "make".to_string(),
+            ..Default::default()
+        };
+
+        let before = fingerprint(&build, tmp.path(), Some("a")).unwrap();
+        assert!(before.is_some());
+        assert_eq!(before, fingerprint(&build, tmp.path(), Some("b")).unwrap());
+        assert!(!outputs_exist(&build, tmp.path()));
+
+        // Only matching files matter
+        std::fs::write(tmp.path().join("README.md"), "more docs").unwrap();
+        assert_eq!(before, fingerprint(&build, tmp.path(), None).unwrap());
+        std::fs::write(tmp.path().join("src/bin/main.rs"), "fn main() { }").unwrap();
+        assert_ne!(before, fingerprint(&build, tmp.path(), None).unwrap());
+
+        let plain_a = fingerprint(&plain, tmp.path(), Some("a")).unwrap();
+        assert_ne!(plain_a, fingerprint(&plain, tmp.path(), Some("b")).unwrap());
+        assert_eq!(fingerprint(&plain, tmp.path(), None).unwrap(), None);
+    }
+}
@@ -0,0 +1,290 @@
+use std::{path::Path, time::UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::{
+    cache::{dir_size, Cache},
+    logs::Stage,
+    manager::{get_package_info, Manager, ManagerError, StepResult, STAGING_PREFIX},
+    state::{now, InstallRecord},
+};
+
+/// Directories usually holding build outputs, used to estimate what `clean` reclaims
+const BUILD_OUTPUTS: &[&str] = &["target", "node_modules", "build", "dist"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GcKind {
+    Orphan,       // Staging directory left behind by an interrupted install or update
+    Untracked,    // Package directory without an install record, only reported
+    StaleRecord,  // Install record without a directory
+    UnusedMirror, // Cache mirror not used by any installed package
+    Clean,        // Installed package with `clean` commands
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GcEntry {
+    pub kind: GcKind,
+    pub target: String,          // Package id, directory or mirror url
+    pub size: u64,               // Reclaimable bytes, estimated for `clean`
+    pub skipped: Option<String>, // Why nothing was done
+    pub steps: Vec<StepResult>,  // Executed `clean` commands
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub entries: Vec<GcEntry>,
+    pub reclaimed: u64, // Total bytes, or the estimate for a dry run
+}
+
+/// Latest modification time of `path` and its direct children
+fn last_touched(path: &Path) -> u64 {
+    let mtime = |p: &Path| {
+        p.metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    };
+
+    let children = std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| mtime(&e.path()));
+    children.chain([mtime(path)]).max().unwrap_or_default()
+}
+
+fn entry(kind: GcKind, target: impl Into<String>, size: u64) -> GcEntry {
+    GcEntry {
+        kind,
+        target: target.into(),
+        size,
+        skipped: None,
+        steps: vec![],
+    }
+}
+
+impl Manager {
+    /// Reclaims disk space, or with `dry_run` only reports what would be reclaimed.
+    ///
+    /// Nothing touched in the last `keep_recent` seconds is removed or cleaned.
+    pub fn gc(&self, dry_run: bool, keep_recent: u64) -> Result<GcReport, ManagerError> {
+        let _lock = self.lock()?;
+        let threshold = now().saturating_sub(keep_recent);
+        let recent = |touched: u64| (touched > threshold).then(|| "touched recently".to_string());
+        let mut entries = vec![];
+
+        if self.repos.exists() {
+            // Staging directories of interrupted installs and updates
+            let io = || ManagerError::io(&self.repos);
+            for dir in std::fs::read_dir(&self.repos).map_err(io())? {
+                let path = dir.map_err(io())?.path();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if !name.starts_with(STAGING_PREFIX) || !path.is_dir() {
+                    continue;
+                }
+
+                let size = dir_size(&path).map_err(ManagerError::io(&path))?;
+                let mut orphan = entry(GcKind::Orphan, name, size);
+                orphan.skipped = recent(last_touched(&path));
+                if orphan.skipped.is_none() && !dry_run {
+                    std::fs::remove_dir_all(&path).map_err(ManagerError::io(&path))?;
+                }
+                entries.push(orphan);
+            }
+
+            // Packages put in place by hand are runnable, so they are left for `remove`
+            let ids = self.installed_ids()?;
+            for id in ids.iter() {
+                if !InstallRecord::path(&self.state, id).exists() {
+                    let path = self.repos.join(id);
+                    let size = dir_size(&path).map_err(ManagerError::io(&path))?;
+                    let mut untracked = entry(GcKind::Untracked, id, size);
+                    untracked.skipped = Some("no install record, use `remove`".to_string());
+                    entries.push(untracked);
+                }
+            }
+
+            for id in ids {
+                let Some(record) = InstallRecord::load(&self.state, &id)? else {
+                    continue;
+                };
+                let path = self.repos.join(&id);
+                let Ok(package) = get_package_info(&path) else {
+                    continue;
+                };
+                if package.clean.is_empty() {
+                    continue;
+                }
+
+                let estimate = BUILD_OUTPUTS
+                    .iter()
+                    .map(|d| path.join(d))
+                    .filter(|p| p.is_dir())
+                    .map(|p| dir_size(&p))
+                    .sum::<Result<u64, _>>()
+                    .map_err(ManagerError::io(&path))?;
+                let mut clean = entry(GcKind::Clean, id, estimate);
+                clean.skipped = recent(record.updated_at.max(last_touched(&path)));
+                if clean.skipped.is_none() && !dry_run {
+                    let before = dir_size(&path).map_err(ManagerError::io(&path))?;
+                    let log = self.log(&clean.target, Stage::Clean);
+                    clean.steps =
+                        self.run_steps(&package.clean, &path, self.opts.quiet, Some(&log));
+                    let after = dir_size(&path).map_err(ManagerError::io(&path))?;
+                    clean.size = before.saturating_sub(after);
+                }
+                entries.push(clean);
+            }
+        }
+
+        // Records left behind by packages removed by hand
+        if self.state.exists() {
+            let io = || ManagerError::io(&self.state);
+            for user in std::fs::read_dir(&self.state).map_err(io())? {
+                let user = user.map_err(io())?;
+                for record in std::fs::read_dir(user.path()).map_err(io())? {
+                    let record = record.map_err(io())?.path();
+                    // Other files, like active versions, belong to a package, not a checkout
+                    if record.extension().is_none_or(|e| e != "yml") {
+                        continue;
+                    }
+                    let Some(name) = record.file_stem() else {
+                        continue;
+                    };
+                    let id = format!(
+                        "{}/{}",
+                        user.file_name().to_string_lossy(),
+                        name.to_string_lossy()
+                    );
+                    if !self.repos.join(&id).exists() {
+                        let mut stale = entry(GcKind::StaleRecord, id, 0);
+                        stale.skipped = recent(last_touched(&record));
+                        if stale.skipped.is_none() && !dry_run {
+                            InstallRecord::delete(&self.state, &stale.target)?;
+                        }
+                        entries.push(stale);
+                    }
+                }
+            }
+        }
+
+        let cache = Cache::new(&self.cache, true);
+        let used = self.used_remotes()?;
+        for mirror in cache.list().map_err(ManagerError::io(&self.cache))? {
+            if used.contains(&mirror.url) {
+                continue;
+            }
+
+            let mut unused = entry(GcKind::UnusedMirror, &mirror.url, mirror.size);
+            unused.skipped = recent(mirror.fetched_at);
+            if unused.skipped.is_none() && !dry_run {
+                std::fs::remove_dir_all(&mirror.path).map_err(ManagerError::io(&mirror.path))?;
+            }
+            entries.push(unused);
+        }
+
+        Ok(GcReport {
+            dry_run,
+            reclaimed: entries
+                .iter()
+                .filter(|e| e.skipped.is_none())
+                .map(|e| e.size)
+                .sum(),
+            entries,
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::{
+        manager::Options,
+        package_index::PackageSource,
+        state::{InstallRecord, SourceRecord},
+    };
+
+    use super::*;
+
+    #[test]
+    fn dry_run_recent_and_clean() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("tool");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(
+            source.join("run.yml"),
+            "name: Tool\ndescription: test\nauthors: []\n\
+             init:\n  global:\n    - program: sh\n      args: [-c, mkdir build && echo out > build/out]\n\
+             clean:\n  global:\n    - program: rm\n      args: [-r, build]\n\
+             run:\n  default:\n    program: tool\n",
+        )
+        .unwrap();
+        let manager = Manager::new(tmp.path().join("home"))
+            .unwrap()
+            .with_options(Options {
+                quiet: true,
+                offline: true,
+            });
+        let installed = manager
+            .install(&PackageSource::new_local(&source), false)
+            .unwrap()
+            .path;
+
+        let orphan = manager.repos.join(".tmp1234");
+        std::fs::create_dir_all(orphan.join("package")).unwrap();
+        std::fs::write(orphan.join("package/file"), "left behind").unwrap();
+        let untracked = manager.repos.join("hand/made");
+        std::fs::create_dir_all(&untracked).unwrap();
+        InstallRecord::new("gone/pkg".to_string(), SourceRecord::Local(source), None)
+            .save(&manager.state)
+            .unwrap();
+
+        let kinds = |report: &GcReport| -> Vec<_> {
+            let mut kinds: Vec<_> = report
+                .entries
+                .iter()
+                .map(|e| (e.kind, e.target.clone(), e.skipped.is_some()))
+                .collect();
+            kinds.sort_by_key(|(_, target, _)| target.clone());
+            kinds
+        };
+
+        // Everything was touched just now
+        let report = manager.gc(false, 3600).unwrap();
+        assert!(report.entries.iter().all(|e| e.skipped.is_some()));
+        assert_eq!(report.reclaimed, 0);
+
+        let report = manager.gc(true, 0).unwrap();
+        assert_eq!(
+            kinds(&report),
+            [
+                (GcKind::Orphan, ".tmp1234".to_string(), false),
+                (GcKind::Clean, "_local/tool".to_string(), false),
+                (GcKind::StaleRecord, "gone/pkg".to_string(), false),
+                (GcKind::Untracked, "hand/made".to_string(), true),
+            ]
+        );
+        assert!(report.reclaimed > 0);
+        assert!(orphan.exists() && installed.join("build").exists());
+        assert!(InstallRecord::load(&manager.state, "gone/pkg")
+            .unwrap()
+            .is_some());
+
+        let report = manager.gc(false, 0).unwrap();
+        assert!(!report.dry_run && report.reclaimed > 0);
+        assert!(!orphan.exists());
+        assert!(!installed.join("build").exists());
+        assert!(InstallRecord::load(&manager.state, "gone/pkg")
+            .unwrap()
+            .is_none());
+        // Packages without a record are only reported
+        assert!(untracked.exists());
+        assert_eq!(
+            manager.installed_ids().unwrap(),
+            ["_local/tool", "hand/made"]
+        );
+    }
+}
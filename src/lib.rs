@@ -1,3 +1,24 @@
+pub mod archive;
+pub mod bundle;
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod executor;
+pub mod fingerprint;
+pub mod gc;
+pub mod lock;
+pub mod logs;
+pub mod manager;
+pub mod manifest;
+pub mod outdated;
+pub mod output;
 pub mod package;
 pub mod package_index;
-pub mod manager;
\ No newline at end of file
+pub mod semver;
+pub mod service;
+pub mod shim;
+pub mod state;
+pub mod target;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod vars;
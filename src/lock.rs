@@ -0,0 +1,106 @@
+use std::{fs::File, path::Path};
+
+/// Advisory lock on a file, shared by all processes using the same data directory.
+///
+/// Released when dropped, or when the process dies. On platforms without `flock`
+/// nothing is locked.
+#[derive(Debug)]
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Takes the lock, creating the file if needed.
+    ///
+    /// If another process holds it, `on_wait` is called before blocking until it's released.
+    pub fn acquire(path: &Path, on_wait: impl FnOnce()) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        if !try_lock(&file)? {
+            on_wait();
+            lock(&file)?;
+        }
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(unix)]
+fn flock(file: &File, operation: i32) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the descriptor is valid while `file` is borrowed
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        return Ok(true);
+    }
+
+    let error = std::io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(error),
+    }
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File) -> std::io::Result<bool> {
+    flock(file, libc::LOCK_EX | libc::LOCK_NB)
+}
+
+#[cfg(unix)]
+fn lock(file: &File) -> std::io::Result<()> {
+    loop {
+        match flock(file, libc::LOCK_EX) {
+            Ok(_) => return Ok(()),
+            // Interrupted by a signal, try again
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_: &File) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn lock(_: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn waits_for_holder() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("lock");
+        let waited = AtomicBool::new(false);
+
+        let held = FileLock::acquire(&path, || panic!("nobody holds the lock")).unwrap();
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                FileLock::acquire(&path, || waited.store(true, Ordering::SeqCst)).unwrap()
+            });
+            std::thread::sleep(Duration::from_millis(200));
+            assert!(!waiter.is_finished());
+
+            drop(held);
+            waiter.join().unwrap();
+        });
+        assert!(waited.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{manager::StepResult, package::Command};
+
+/// How many logs are kept for each package, older ones are deleted
+pub const MAX_LOGS: usize = 50;
+
+/// What a logged execution was doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Init,
+    Check,
+    Run,
+    Clean,
+    Service,
+    Uninstall,
+    #[value(name = "post_update")]
+    PostUpdate,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Init => "init",
+            Stage::Check => "check",
+            Stage::Run => "run",
+            Stage::Clean => "clean",
+            Stage::Service => "service",
+            Stage::Uninstall => "uninstall",
+            Stage::PostUpdate => "post_update",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            Stage::Init,
+            Stage::Check,
+            Stage::Run,
+            Stage::Clean,
+            Stage::Service,
+            Stage::Uninstall,
+            Stage::PostUpdate,
+        ]
+        .into_iter()
+        .find(|s| s.name() == name)
+    }
+}
+
+/// Log of one execution stage of a package, `MAIN_PATH/logs/<id>/<millis>-<stage>.log`.
+///
+/// The file is created when the first command starts, so stages without commands
+/// leave nothing behind.
+#[derive(Debug, Clone)]
+pub struct Log {
+    dir: PathBuf,
+    path: PathBuf,
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl Log {
+    pub fn new(logs_dir: &Path, id: &str, stage: Stage) -> Self {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let dir = logs_dir.join(id);
+        Self {
+            path: dir.join(format!("{millis}-{}.log", stage.name())),
+            dir,
+            file: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&self, data: &[u8]) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            std::fs::create_dir_all(&self.dir)?;
+            *file = Some(File::create(&self.path)?);
+            rotate(&self.dir, MAX_LOGS)?;
+        }
+
+        file.as_mut().unwrap().write_all(data)
+    }
+
+    /// Writes the header of a command about to run
+    pub fn start(&self, command: &Command) -> std::io::Result<()> {
+        self.write(format!("$ {}\n", plain(command)).as_bytes())
+    }
+
+    /// Writes the outcome of a finished command
+    pub fn finish(&self, step: &StepResult, duration: Duration) -> std::io::Result<()> {
+        let status = match (&step.error, step.code) {
+            (Some(error), _) => error.clone(),
+            (None, Some(code)) => format!("exit code {code}"),
+            (None, None) => "killed by a signal".to_string(),
+        };
+        self.write(format!("[{status} after {:.2}s]\n\n", duration.as_secs_f64()).as_bytes())
+    }
+
+    /// Writes a note about a command skipped because nothing it depends on changed
+    pub fn skip(&self, command: &Command) -> std::io::Result<()> {
+        self.write(format!("$ {}\n[unchanged, skipped]\n\n", plain(command)).as_bytes())
+    }
+
+    /// Writer appending output of a command to the log
+    pub fn writer(&self) -> LogWriter {
+        LogWriter(self.clone())
+    }
+}
+
+/// Command line as written in the log, without colors
+fn plain(command: &Command) -> String {
+    let mut text = command.program.clone();
+    for arg in &command.args {
+        if arg.contains(' ') {
+            text.push_str(&format!(" \"{arg}\""));
+        } else {
+            text.push_str(&format!(" {arg}"));
+        }
+    }
+    text
+}
+
+pub struct LogWriter(Log);
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Copies everything from `from` into both `to` and the log
+pub fn tee(mut from: impl Read, mut to: impl Write, log: &Log) -> std::io::Result<()> {
+    let mut log = log.writer();
+    let mut buf = [0; 8192];
+    loop {
+        let n = from.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        // The user closing our output must not stop the log
+        let _ = to.write_all(&buf[..n]).and_then(|_| to.flush());
+        log.write_all(&buf[..n])?;
+    }
+}
+
+/// Information about a single log file, as shown by `logs`
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub path: PathBuf,
+    pub stage: Stage,
+    pub created_at: u64, // Unix timestamp
+    pub size: u64,
+}
+
+/// Logs in `dir`, oldest first, optionally only of one stage
+pub fn list(dir: &Path, stage: Option<Stage>) -> std::io::Result<Vec<LogEntry>> {
+    let mut logs = vec![];
+    if !dir.exists() {
+        return Ok(logs);
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some((millis, log_stage)) = name
+            .strip_suffix(".log")
+            .and_then(|n| n.split_once('-'))
+            .and_then(|(m, s)| Some((m.parse::<u64>().ok()?, Stage::from_name(s)?)))
+        else {
+            continue;
+        };
+        if stage.is_some_and(|s| s != log_stage) {
+            continue;
+        }
+
+        logs.push(LogEntry {
+            path: entry.path(),
+            stage: log_stage,
+            created_at: millis / 1000,
+            size: entry.metadata()?.len(),
+        });
+    }
+
+    logs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(logs)
+}
+
+/// Deletes the oldest logs in `dir` so that at most `keep` remain
+pub fn rotate(dir: &Path, keep: usize) -> std::io::Result<()> {
+    let logs = list(dir, None)?;
+    for log in &logs[..logs.len().saturating_sub(keep)] {
+        std::fs::remove_file(&log.path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(command: &Command, code: i32) -> StepResult {
+        StepResult {
+            command: command.clone(),
+            success: code == 0,
+            code: Some(code),
+            error: None,
+            timed_out: false,
+            attempts: 1,
+            cached: false,
+            pid: None,
+        }
+    }
+
+    #[test]
+    fn write_and_list() {
+        let tmp = tempfile::tempdir().unwrap();
+        let command = Command {
+            program: "make".to_string(),
+            ..Default::default()
+        };
+
+        let unused = Log::new(tmp.path(), "user/tool", Stage::Check);
+        assert!(!unused.path().exists());
+
+        let log = Log::new(tmp.path(), "user/tool", Stage::Init);
+        log.start(&command).unwrap();
+        tee(&b"built\n"[..], std::io::sink(), &log).unwrap();
+        log.finish(&step(&command, 2), Duration::from_millis(1500))
+            .unwrap();
+
+        let text = std::fs::read_to_string(log.path()).unwrap();
+        assert_eq!(text, "$ make\nbuilt\n[exit code 2 after 1.50s]\n\n");
+
+        let logs = list(&tmp.path().join("user/tool"), None).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].stage, Stage::Init);
+        assert!(list(&tmp.path().join("user/tool"), Some(Stage::Run))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn rotation() {
+        let tmp = tempfile::tempdir().unwrap();
+        for millis in 1000..1005 {
+            std::fs::write(tmp.path().join(format!("{millis}-run.log")), "").unwrap();
+        }
+
+        rotate(tmp.path(), 2).unwrap();
+        let logs = list(tmp.path(), None).unwrap();
+        assert_eq!(logs.len(), 2);
+        assert!(logs[0].path.ends_with("1003-run.log"));
+    }
+}
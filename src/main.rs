@@ -1,39 +1,1039 @@
-use clap::Parser;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::{CommandFactory, Parser};
 use colored::*;
-use run_that::manager::{get_package_info, REPOS_PATH};
+use run_that::{
+    bundle::BundleInfo,
+    config::{Config, ConfigError},
+    error::{Error, ErrorKind},
+    gc::GcKind,
+    logs::LogEntry,
+    manager::{
+        get_package_info, CheckReport, DoctorReport, Event, InitStatus, InstallOutcome, Manager,
+        ManagerError, Options, PackagePreview, ServiceStatus, StepResult, MAIN_PATH,
+    },
+    manifest::{write_manifest, ManifestError, ManifestFormat},
+    outdated::{Changelog, OutdatedEntry, Pin},
+    output::OutputFormat,
+    package::Package,
+    package_index::{split_version, GitSource, PackageSource},
+    shim,
+    state::{now, Revision},
+};
+use serde::Serialize;
 
 mod cli;
+mod completions;
+
+fn main() -> ExitCode {
+    completions::handle_request();
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = cli::GlobalArgs::parse();
+    // `--format` wins over the config, which may be broken, so its errors are reported later
+    let manager = Manager::from_env();
+    let format = args
+        .format
+        .or_else(|| manager.as_ref().ok().map(|m| m.config().format))
+        .unwrap_or_default();
+    format.setup_colors();
+
+    let verbose = args.verbose;
+    let result = match args.action {
+        cli::GlobalAction::Config(config) => configure(config.action, format),
+        action => manager
+            .map_err(Into::into)
+            .and_then(|manager| run(cli::GlobalArgs { action, ..args }, format, manager)),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            report_error(format, verbose, &e);
+            ExitCode::from(e.kind().exit_code())
+        }
+    }
+}
+
+/// Prints the error with a hint, causes are only shown with `--verbose` in the human format
+fn report_error(format: OutputFormat, verbose: bool, error: &Error) {
+    #[derive(Serialize)]
+    struct ErrorData {
+        message: String,
+        kind: ErrorKind,
+        exit_code: u8,
+        hint: Option<String>,
+        causes: Vec<String>,
+    }
+
+    let data = ErrorData {
+        message: error.to_string(),
+        kind: error.kind(),
+        exit_code: error.kind().exit_code(),
+        hint: error.hint(),
+        causes: error.causes(),
+    };
+    emit(format, "error", &data, || {
+        eprintln!("{} {}", "Error:".bright_red(), data.message);
+        if verbose {
+            for cause in &data.causes {
+                eprintln!("  caused by: {cause}");
+            }
+        }
+        if let Some(hint) = &data.hint {
+            eprintln!("{} {hint}", "hint:".bright_cyan());
+        }
+        if !verbose && !data.causes.is_empty() {
+            eprintln!("Run again with --verbose to see the causes");
+        }
+    });
+}
+
+/// Prints `data` in the machine readable `format`, or calls `human` for the human format
+fn emit<T: Serialize>(format: OutputFormat, kind: &str, data: &T, human: impl FnOnce()) {
+    match format.render(kind, data) {
+        Some(text) => println!("{}", text.trim_end()),
+        None => human(),
+    }
+}
+
+fn print_steps(title: &str, steps: &[StepResult]) {
+    if steps.is_empty() {
+        return;
+    }
+
+    println!("{title}");
+    for step in steps {
+        let mark = if step.cached {
+            "cached".bright_blue()
+        } else if step.success {
+            "ok".bright_green()
+        } else if !step.is_fatal() {
+            "ignored".bright_yellow()
+        } else {
+            "failed".bright_red()
+        };
+        let mut notes = vec![];
+        if let Some(error) = &step.error {
+            notes.push(error.clone());
+        } else if let (false, Some(code)) = (step.success, step.code) {
+            notes.push(format!("exit code {code}"));
+        }
+        if step.attempts > 1 {
+            notes.push(format!("{} attempts", step.attempts));
+        }
+
+        let name = match &step.command.description {
+            Some(description) => description.clone(),
+            None => step.command.to_string(),
+        };
+        if notes.is_empty() {
+            println!("  [{mark}] {name}");
+        } else {
+            println!("  [{mark}] {name} ({})", notes.join(", "));
+        }
+    }
+}
+
+fn print_init_status(init: &[InitStatus]) {
+    if init.is_empty() {
+        return;
+    }
+
+    println!("Init steps:");
+    for status in init {
+        let mark = if status.cached {
+            "cached".bright_blue()
+        } else {
+            "not cached".bright_yellow()
+        };
+        let name = match &status.command.description {
+            Some(description) => description.clone(),
+            None => status.command.to_string(),
+        };
+        println!("  [{mark}] {name}");
+    }
+}
+
+fn print_history(history: &[Revision]) {
+    if history.len() < 2 {
+        return;
+    }
+
+    println!("Revisions, newest first:");
+    for (i, revision) in history.iter().rev().enumerate() {
+        let tag = match &revision.tag {
+            Some(tag) => format!(" {}", tag.bright_blue()),
+            None => String::new(),
+        };
+        let current = if i == 0 {
+            format!(" {}", "(current)".bright_green())
+        } else {
+            String::new()
+        };
+        println!(
+            "  {}{tag} {}{current}",
+            short(&revision.commit).yellow(),
+            human_age(revision.at)
+        );
+    }
+}
+
+fn print_check_report(report: &CheckReport) {
+    print_steps(
+        &format!("Checks of {}:", report.package.bright_magenta()),
+        &report.steps,
+    );
+    if report.passed {
+        println!("{}", "All checks passed".bright_green());
+    } else {
+        println!("{}", "Some checks failed".bright_red());
+    }
+}
+
+fn print_started(status: &ServiceStatus) {
+    println!(
+        "Started {} with pid {}, output goes to {:?}",
+        status.record.id.bright_magenta(),
+        status.record.pid,
+        status.record.log
+    );
+}
+
+fn human_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+/// Prints the file and then everything appended to it, until interrupted
+fn follow(path: &Path) -> std::io::Result<()> {
+    let mut file = std::fs::File::open(path)?;
+    let mut stdout = std::io::stdout();
+    loop {
+        if std::io::copy(&mut file, &mut stdout)? == 0 {
+            stdout.flush()?;
+            std::thread::sleep(Duration::from_millis(250));
+        }
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// How long ago `timestamp` was, e.g. `3 days ago`
+fn human_age(timestamp: u64) -> String {
+    let secs = now().saturating_sub(timestamp);
+    let (value, unit) = match secs {
+        0..=59 => (secs, "second"),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+
+    if value == 1 {
+        format!("1 {unit} ago")
+    } else {
+        format!("{value} {unit}s ago")
+    }
+}
+
+fn print_doctor(report: &DoctorReport) {
+    println!("Data directory: {:?}", report.main_path);
+
+    if report.bin_on_path {
+        println!(
+            "{} launchers in {:?} are on PATH",
+            "ok".bright_green(),
+            report.bin_path
+        );
+    } else {
+        println!(
+            "{} launchers in {:?} are not on PATH",
+            "warning".yellow(),
+            report.bin_path
+        );
+        let bin = report.bin_path.display();
+        let shell = std::env::var("SHELL").unwrap_or_default();
+        if cfg!(windows) {
+            println!("  Add it for the current user with:");
+            println!("    setx PATH \"%PATH%;{bin}\"");
+        } else if shell.ends_with("fish") {
+            println!("  Add it with:");
+            println!("    fish_add_path {bin}");
+        } else {
+            let rc = if shell.ends_with("zsh") {
+                "~/.zshrc"
+            } else {
+                "~/.bashrc"
+            };
+            println!("  Add this line to {rc} and restart your shell:");
+            println!("    export PATH=\"{bin}:$PATH\"");
+        }
+    }
+
+    for shim in &report.broken_shims {
+        println!(
+            "{} launcher {:?} points to {}, which is not installed",
+            "warning".yellow(),
+            shim.path,
+            shim.id.bright_magenta()
+        );
+    }
+}
+
+fn print_preview(preview: &PackagePreview) {
+    println!(
+        "{}\n{}",
+        format!("Package info of {}:", preview.address).bright_magenta(),
+        preview.package
+    );
+    println!(
+        "Manifest: {} at commit {}",
+        preview.manifest,
+        short(&preview.commit)
+    );
+
+    if preview.checks.is_empty() {
+        println!("No checks run before installing on this platform");
+    } else {
+        println!("Checks run before installing on this platform:");
+        for check in &preview.checks {
+            println!("  {check}");
+        }
+    }
+
+    if preview.supported {
+        println!("Supported on this platform: {}", "yes".bright_green());
+    } else {
+        println!("Supported on this platform: {}", "no".bright_red());
+    }
+}
+
+fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(12)]
+}
+
+fn print_outdated(entries: &[OutdatedEntry]) {
+    for entry in entries {
+        if let Some(error) = &entry.error {
+            eprintln!(
+                "{}: could not check {}: {error}",
+                "warning".yellow(),
+                entry.id.bright_magenta()
+            );
+        }
+    }
+    if !entries.iter().any(|e| e.is_outdated()) {
+        if entries.iter().all(|e| e.error.is_none()) {
+            println!("All git packages are up to date");
+        }
+        return;
+    }
+
+    for entry in entries.iter().filter(|e| e.is_outdated()) {
+        match &entry.pin {
+            Pin::Branch(branch) => println!(
+                "{} is {} commit{} behind {}",
+                entry.id.bright_magenta(),
+                entry.behind,
+                if entry.behind == 1 { "" } else { "s" },
+                branch.bright_blue()
+            ),
+            Pin::Tag(tag) => println!(
+                "{} {} has newer tags: {}",
+                entry.id.bright_magenta(),
+                tag.bright_blue(),
+                entry.newer_tags.join(", ").bright_green()
+            ),
+            Pin::Commit(_) => {}
+        }
+    }
+}
+
+fn print_changelog(changelog: &Changelog) {
+    let (Some(from), Some(to)) = (&changelog.from, &changelog.to) else {
+        println!(
+            "{} isn't installed from git, it has no changelog",
+            changelog.id.bright_magenta()
+        );
+        return;
+    };
+    if changelog.commits.is_empty() {
+        println!("{} is up to date", changelog.id.bright_magenta());
+        return;
+    }
+
+    println!(
+        "{}",
+        format!(
+            "Changes of {} from {} to {}:",
+            changelog.id,
+            short(from),
+            short(to)
+        )
+        .bright_magenta()
+    );
+    for commit in &changelog.commits {
+        println!(
+            "{} {} ({})",
+            short(&commit.commit).yellow(),
+            commit.summary,
+            commit.author
+        );
+    }
+}
+
+/// Prints the result of installing a package, shared by `install` and `import`
+fn report_install(
+    format: OutputFormat,
+    result: Result<InstallOutcome, ManagerError>,
+) -> Result<(), ManagerError> {
+    let outcome = match result {
+        Err(ManagerError::ChecksFailed(report)) => {
+            if format.is_human() {
+                print_check_report(&report);
+            }
+            return Err(ManagerError::ChecksFailed(report));
+        }
+        result => result?,
+    };
+
+    emit(format, "install", &outcome, || {
+        if !outcome.installed {
+            println!(
+                "Package {} is already installed, use --force to reinstall it",
+                outcome.id.bright_magenta()
+            );
+            return;
+        }
+
+        if let Some(checks) = &outcome.checks {
+            print_steps("Checks:", &checks.steps);
+        }
+        print_steps("Init:", &outcome.init);
+        println!(
+            "Installed {} to {:?}",
+            outcome.id.bright_magenta(),
+            outcome.path
+        );
+    });
+
+    Ok(())
+}
+
+/// Asks the user a yes or no question on stderr, anything but yes is no
+fn confirm(question: &str) -> std::io::Result<bool> {
+    eprint!("{question} [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// `config` commands, which work without the manager, so an invalid config can be repaired
+fn configure(action: cli::ConfigAction, format: OutputFormat) -> Result<ExitCode, Error> {
+    let path = MAIN_PATH.join("config.yml");
+    let (mut config, invalid) = Config::load_or_default(&path);
+    if let Some(e) = &invalid {
+        let reason = std::error::Error::source(e).map_or(e.to_string(), |c| c.to_string());
+        eprintln!(
+            "{} {path:?} is invalid, using the defaults: {reason}",
+            "warning".yellow()
+        );
+    }
+
+    #[derive(Serialize)]
+    struct Setting {
+        key: String,
+        value: Option<String>, // `null` after `unset`
+    }
+
+    let setting = match action {
+        cli::ConfigAction::List => {
+            let settings: Vec<_> = config
+                .with_env()?
+                .list()
+                .into_iter()
+                .map(|(key, value)| Setting {
+                    key,
+                    value: Some(value),
+                })
+                .collect();
+            emit(format, "config", &settings, || {
+                for setting in &settings {
+                    let value = setting.value.as_deref().unwrap_or_default();
+                    println!("{} = {value}", setting.key.bright_magenta());
+                }
+            });
+            return Ok(ExitCode::SUCCESS);
+        }
+        cli::ConfigAction::Get { key } => {
+            let value = config.with_env()?.get(&key)?;
+            (
+                value.clone(),
+                Setting {
+                    key,
+                    value: Some(value),
+                },
+            )
+        }
+        cli::ConfigAction::Set { key, value } => {
+            config.set(&key, &value)?;
+            save_config(&config, &path, invalid)?;
+            (
+                format!("Set {} = {value}", key.bright_magenta()),
+                Setting {
+                    key,
+                    value: Some(value),
+                },
+            )
+        }
+        cli::ConfigAction::Unset { key } => {
+            config.unset(&key)?;
+            save_config(&config, &path, invalid)?;
+            (
+                format!("Unset {}", key.bright_magenta()),
+                Setting { key, value: None },
+            )
+        }
+    };
+
+    let (message, setting) = setting;
+    emit(format, "config_value", &setting, || println!("{message}"));
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Saves a changed config, an invalid file it replaces is kept next to it
+fn save_config(config: &Config, path: &Path, invalid: Option<ConfigError>) -> Result<(), Error> {
+    if invalid.is_some() && path.exists() {
+        let backup = path.with_extension("yml.invalid");
+        std::fs::rename(path, &backup).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        eprintln!(
+            "{} the invalid config was moved to {backup:?}",
+            "warning".yellow()
+        );
+    }
+
+    Ok(config.save(path)?)
+}
 
-    #[cfg(debug_assertions)]
-    println!("{args:?}");
+fn run(args: cli::GlobalArgs, format: OutputFormat, manager: Manager) -> Result<ExitCode, Error> {
+    let manager = manager
+        .with_options(Options {
+            quiet: !format.is_human(),
+            offline: args.offline,
+        })
+        .on_event(move |event| match (event, format.is_human()) {
+            (Event::Fetching(source), true) => eprintln!("Fetching {source}"),
+            (Event::WaitingForLock, true) => {
+                eprintln!("Waiting for another run-that process to finish")
+            }
+            _ => {}
+        });
 
     match args.action {
-        cli::GlobalAction::Check => println!("Check"),
-        cli::GlobalAction::Install(_args) => println!("Install"),
-        cli::GlobalAction::Remove => println!("Remove"),
+        cli::GlobalAction::Check(args) => {
+            let path = match args.name {
+                Some(name) => manager.repos_path().join(manager.resolve_id(&name)?),
+                None => args.path.unwrap_or_else(|| PathBuf::from(".")),
+            };
+
+            let report = manager.check(&path)?;
+            emit(format, "check", &report, || print_check_report(&report));
+            if !report.passed {
+                return Ok(ExitCode::from(ErrorKind::CheckFailed.exit_code()));
+            }
+        }
+        cli::GlobalAction::Install(args) => {
+            let location = match (&args.address, &args.path) {
+                (Some(address), _) => address.clone(),
+                (None, Some(path)) => path.to_string_lossy().into_owned(),
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+
+            let source = match PackageSource::new_archive(&location, args.sha256.clone()) {
+                Some(source) => source,
+                // Only archives have a checksum to compare with, exits like other usage errors
+                None if args.sha256.is_some() => {
+                    let mut command = cli::GlobalArgs::command();
+                    command.build();
+                    command
+                        .find_subcommand_mut("install")
+                        .expect("install is a subcommand")
+                        .error(
+                            clap::error::ErrorKind::ArgumentConflict,
+                            format!("--sha256 needs an archive, `{location}` is not one"),
+                        )
+                        .exit()
+                }
+                None if args.address.is_some() => {
+                    PackageSource::new_git(location, manager.config())?
+                }
+                None => PackageSource::new_local(location),
+            };
+
+            report_install(format, manager.install(&source, args.force))?;
+        }
+        cli::GlobalAction::Export(args) => {
+            #[derive(Serialize)]
+            struct Exported<'a> {
+                path: &'a PathBuf,
+                bundle: &'a BundleInfo,
+            }
+
+            let output = args.output.unwrap_or_else(|| {
+                let name = args.name.rsplit('/').next().unwrap_or(&args.name);
+                PathBuf::from(format!("{name}.tar.gz"))
+            });
+            let info = manager.export(&args.name, &output)?;
+            emit(
+                format,
+                "export",
+                &Exported {
+                    path: &output,
+                    bundle: &info,
+                },
+                || println!("Exported {} to {output:?}", info.record.id.bright_magenta()),
+            );
+        }
+        cli::GlobalAction::Import(args) => {
+            report_install(format, manager.import(&args.bundle, args.force))?;
+        }
+        cli::GlobalAction::Remove(args) => {
+            if manager.config().confirm && !args.yes && !confirm(&format!("Remove {}?", args.name))?
+            {
+                return Ok(ExitCode::FAILURE);
+            }
+
+            let outcome = manager.remove(&args.name, args.force)?;
+            emit(format, "remove", &outcome, || {
+                print_steps("Uninstall:", &outcome.uninstall);
+                for shim in &outcome.shims {
+                    println!("Removed launcher {:?}", shim.path);
+                }
+                println!("Removed {}", outcome.id.bright_magenta())
+            });
+        }
+        cli::GlobalAction::Update(args) => {
+            let outcome = manager.update(&args.name, args.force_init)?;
+            emit(format, "update", &outcome, || {
+                if outcome.changed {
+                    print_steps("Init:", &outcome.init);
+                    print_steps("Post update:", &outcome.post_update);
+                    println!("Updated {}", outcome.id.bright_magenta());
+                } else {
+                    println!("{} is up to date", outcome.id.bright_magenta());
+                }
+            });
+        }
+        cli::GlobalAction::Rollback(args) => {
+            let outcome = manager.rollback(&args.name, args.to.as_deref())?;
+            emit(format, "rollback", &outcome, || {
+                print_steps("Init:", &outcome.init);
+                println!(
+                    "Rolled {} back to {}",
+                    outcome.id.bright_magenta(),
+                    short(&outcome.to.commit).yellow()
+                );
+            });
+        }
+        cli::GlobalAction::Outdated => {
+            if format.is_human() {
+                eprintln!("Fetching remotes of installed packages");
+            }
+            let entries = manager.outdated()?;
+            emit(format, "outdated", &entries, || print_outdated(&entries));
+        }
+        cli::GlobalAction::Changelog(args) => {
+            let changelog = manager.changelog(&args.name)?;
+            emit(format, "changelog", &changelog, || {
+                print_changelog(&changelog)
+            });
+        }
+        cli::GlobalAction::Run(args) => {
+            let outcome = manager.run(&args.name, &args.args, args.params.into_iter().collect())?;
+            emit(format, "run", &outcome, || {
+                // Output of the hooks went to the terminal already, only failures need a note
+                if outcome.post_run.iter().any(|s| s.is_fatal()) {
+                    print_steps("Post run:", &outcome.post_run);
+                }
+            });
+            if !outcome.success {
+                return Ok(outcome
+                    .code
+                    .and_then(|c| u8::try_from(c).ok())
+                    .map(ExitCode::from)
+                    .unwrap_or(ExitCode::FAILURE));
+            }
+        }
+        cli::GlobalAction::Use(args) => {
+            #[derive(Serialize)]
+            struct Active<'a> {
+                package: &'a str,
+                id: &'a str,
+            }
+
+            let id = manager.activate(&args.name)?;
+            let package = split_version(&id).0;
+            emit(format, "active", &Active { package, id: &id }, || {
+                println!(
+                    "{} now runs {}",
+                    package.bright_magenta(),
+                    id.bright_magenta()
+                )
+            });
+        }
+        cli::GlobalAction::Start(args) => {
+            let status =
+                manager.start(&args.name, &args.args, args.params.into_iter().collect())?;
+            emit(format, "service", &status, || print_started(&status));
+        }
+        cli::GlobalAction::Stop(args) => {
+            let outcome = manager.stop(&args.name)?;
+            emit(format, "stop", &outcome, || {
+                if !outcome.was_running {
+                    println!(
+                        "{} was not running, removed its stale pid file",
+                        outcome.id.bright_magenta()
+                    );
+                } else if outcome.killed {
+                    println!(
+                        "Killed {}, it didn't stop in time",
+                        outcome.id.bright_magenta()
+                    );
+                } else {
+                    println!("Stopped {}", outcome.id.bright_magenta());
+                }
+            });
+        }
+        cli::GlobalAction::Restart(args) => {
+            let status = manager.restart(&args.name)?;
+            emit(format, "service", &status, || print_started(&status));
+        }
+        cli::GlobalAction::Status(args) => {
+            let services = manager.status(args.name.as_deref())?;
+            emit(format, "status", &services, || {
+                if services.is_empty() {
+                    println!("No packages are running in the background");
+                }
+                for service in &services {
+                    let state = match service.uptime {
+                        Some(uptime) => format!("up {}", human_duration(uptime)).bright_green(),
+                        None => "stale".bright_red(),
+                    };
+                    println!(
+                        "{} (pid {}) {state}",
+                        service.record.id.bright_magenta(),
+                        service.record.pid
+                    );
+                }
+            });
+        }
         cli::GlobalAction::Show(args) => {
             if args.install_path {
-                println!("Repositories are stored in: {REPOS_PATH:?}");
+                #[derive(Serialize)]
+                struct InstallPath<'a> {
+                    path: &'a Path,
+                }
+
+                emit(
+                    format,
+                    "install_path",
+                    &InstallPath {
+                        path: manager.repos_path(),
+                    },
+                    || println!("Repositories are stored in: {:?}", manager.repos_path()),
+                );
             } else if args.installed_packages {
-                println!("Installed repositories:");
+                let installed = manager.list()?;
+                emit(format, "installed", &installed, || {
+                    println!("Installed repositories:");
+                    // Which of several installed versions runs by default
+                    let versions = |id: &str| {
+                        let package = split_version(id).0;
+                        installed
+                            .iter()
+                            .filter(|p| split_version(&p.id).0 == package)
+                            .count()
+                    };
+                    for package in &installed {
+                        let active = if package.active && versions(&package.id) > 1 {
+                            format!(" {}", "(active)".bright_green())
+                        } else {
+                            String::new()
+                        };
+                        match &package.package {
+                            Some(info) => println!(
+                                "  {}{active} - {}",
+                                package.id.bright_magenta(),
+                                info.description
+                            ),
+                            None => println!(
+                                "  {}{active} - {}",
+                                package.id.bright_magenta(),
+                                "manifest missing".bright_red()
+                            ),
+                        }
+                    }
+                });
             }
         }
         cli::GlobalAction::Info(args) => {
-            let path = if let Some(name) = args.name {
-                REPOS_PATH.join(name)
-            } else if let Some(path) = args.path {
-                path
-            } else {
-                std::env::current_dir().expect("Could not get current directory")
-            };
+            #[derive(Serialize)]
+            struct Info<'a> {
+                path: &'a PathBuf,
+                package: &'a Package,
+            }
 
+            if let Some(address) = args.address {
+                let source = GitSource::new(address, manager.config())?;
+                if format.is_human() {
+                    eprintln!("Fetching {source}");
+                }
+                let preview = manager.preview(&source)?;
+                emit(format, "preview", &preview, || print_preview(&preview));
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            if let Some(name) = args.name {
+                let details = manager.info(&name)?;
+                emit(format, "info", &details, || {
+                    println!("{}\n{}", "Package info:".bright_magenta(), details.package);
+                    print_init_status(&details.init);
+                    if let Some(record) = &details.record {
+                        print_history(&record.history);
+                    }
+                });
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            let path = match args.path {
+                Some(path) => path,
+                None => std::env::current_dir().expect("Could not get current directory"),
+            };
             let package = get_package_info(&path)?;
-            println!("{}\n{}", "Package info:".bright_magenta(), package);
+            emit(
+                format,
+                "info",
+                &Info {
+                    path: &path,
+                    package: &package,
+                },
+                || println!("{}\n{}", "Package info:".bright_magenta(), package),
+            );
         }
+        cli::GlobalAction::Convert(args) => {
+            #[derive(Serialize)]
+            struct Converted {
+                path: Option<PathBuf>, // Written file
+                format: ManifestFormat,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                manifest: Option<String>, // Converted text, when there's no file to write
+            }
+
+            let package = get_package_info(&args.input)?;
+            let to_format = match (args.to, &args.output) {
+                (Some(to_format), _) => to_format,
+                (None, Some(output)) => ManifestFormat::from_path(output)
+                    .ok_or_else(|| ManifestError::UnknownFormat(output.clone()))?,
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+
+            let converted = match args.output {
+                Some(output) => {
+                    write_manifest(&output, to_format, &package)?;
+                    Converted {
+                        path: Some(output),
+                        format: to_format,
+                        manifest: None,
+                    }
+                }
+                None => Converted {
+                    path: None,
+                    format: to_format,
+                    manifest: Some(to_format.render(&package)?),
+                },
+            };
+            emit(format, "convert", &converted, || {
+                match (&converted.path, &converted.manifest) {
+                    (Some(path), _) => println!("Manifest written to {path:?}"),
+                    (None, manifest) => print!("{}", manifest.as_deref().unwrap_or_default()),
+                }
+            });
+        }
+        cli::GlobalAction::Link(args) => {
+            let shim = manager.link(&args.name, args.as_name.as_deref(), args.force)?;
+            emit(format, "link", &shim, || {
+                println!(
+                    "Created {} launching {}",
+                    shim.name.bright_green(),
+                    shim.id.bright_magenta()
+                );
+                if !shim::on_path(manager.bin_path()) {
+                    println!(
+                        "{:?} is not on PATH, run `run-that doctor` to see how to add it",
+                        manager.bin_path()
+                    );
+                }
+            });
+        }
+        cli::GlobalAction::Doctor => {
+            let report = manager.doctor()?;
+            emit(format, "doctor", &report, || print_doctor(&report));
+        }
+        cli::GlobalAction::Cache(args) => {
+            let (kind, mirrors, title) = match args.action {
+                cli::CacheAction::List => ("cache", manager.cache_list()?, "Cached remotes:"),
+                cli::CacheAction::Prune => ("cache_prune", manager.cache_prune()?, "Removed:"),
+            };
+
+            emit(format, kind, &mirrors, || {
+                println!("{title}");
+                for mirror in &mirrors {
+                    println!(
+                        "  {} ({}, fetched {})",
+                        mirror.url.bright_magenta(),
+                        human_size(mirror.size),
+                        human_age(mirror.fetched_at)
+                    );
+                }
+            });
+        }
+        cli::GlobalAction::Gc(args) => {
+            if manager.config().confirm
+                && !args.yes
+                && !args.dry_run
+                && !confirm("Reclaim unused space?")?
+            {
+                return Ok(ExitCode::FAILURE);
+            }
+
+            let report = manager.gc(args.dry_run, args.keep_recent * 3600)?;
+            emit(format, "gc", &report, || {
+                for entry in &report.entries {
+                    let kind = match entry.kind {
+                        GcKind::Orphan => "orphaned directory",
+                        GcKind::Untracked => "package without install record",
+                        GcKind::StaleRecord => "stale install record",
+                        GcKind::UnusedMirror => "unused mirror",
+                        GcKind::Clean => "build outputs",
+                    };
+                    match &entry.skipped {
+                        Some(reason) => println!(
+                            "  {} {kind} {} ({reason})",
+                            "skip".yellow(),
+                            entry.target.bright_magenta()
+                        ),
+                        None => println!(
+                            "  {:>10} {kind} {}",
+                            human_size(entry.size),
+                            entry.target.bright_magenta()
+                        ),
+                    }
+                }
+
+                if report.dry_run {
+                    println!("Would reclaim {}", human_size(report.reclaimed));
+                } else {
+                    println!("Reclaimed {}", human_size(report.reclaimed));
+                }
+            });
+        }
+        cli::GlobalAction::Logs(args) => {
+            let (id, logs) = manager.logs(&args.name, args.stage)?;
+            if !(args.last || args.follow) {
+                #[derive(Serialize)]
+                struct Logs<'a> {
+                    id: &'a str,
+                    logs: &'a [LogEntry],
+                }
+
+                emit(
+                    format,
+                    "logs",
+                    &Logs {
+                        id: &id,
+                        logs: &logs,
+                    },
+                    || {
+                        if logs.is_empty() {
+                            println!("No logs of {}", id.bright_magenta());
+                        }
+                        for log in &logs {
+                            println!(
+                                "{:>16} {:<6} {:>10}  {}",
+                                human_age(log.created_at),
+                                log.stage.name(),
+                                human_size(log.size),
+                                log.path.display()
+                            );
+                        }
+                    },
+                );
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            #[derive(Serialize)]
+            struct Log<'a> {
+                id: &'a str,
+                log: Option<&'a LogEntry>,
+                content: Option<String>,
+            }
+
+            let log = logs.last();
+            // Programs may print anything, invalid UTF-8 shouldn't hide the rest of the log
+            let content = log
+                .map(|l| std::fs::read(&l.path).map_err(|e| Error::Io(l.path.clone(), e)))
+                .transpose()?
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+            if !format.is_human() {
+                emit(
+                    format,
+                    "log",
+                    &Log {
+                        id: &id,
+                        log,
+                        content,
+                    },
+                    || {},
+                );
+            } else if let Some(log) = log {
+                if args.follow {
+                    follow(&log.path).map_err(|e| Error::Io(log.path.clone(), e))?;
+                } else {
+                    print!("{}", content.unwrap_or_default());
+                }
+            } else {
+                println!("No logs of {}", id.bright_magenta());
+            }
+        }
+        cli::GlobalAction::Completions(args) => {
+            completions::write_registration(args.shell, &mut std::io::stdout())?;
+        }
+        // Handled before the manager is built, see `configure`
+        cli::GlobalAction::Config(_) => unreachable!(),
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
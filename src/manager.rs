@@ -1,31 +1,2181 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use once_cell::sync::Lazy;
 use path_absolutize::Absolutize;
+use serde::Serialize;
+use tempfile::TempDir;
 use thiserror::Error;
 
-use crate::package::Package;
+use crate::{
+    archive::ArchiveError,
+    bundle::{self, BundleError, BundleInfo},
+    cache::{Cache, MirrorInfo},
+    config::{Config, ConfigError},
+    error::closest,
+    executor::{Executor, Local, Sink, Step},
+    fingerprint::{self, fingerprint, outputs_exist},
+    lock::FileLock,
+    logs::{self, Log, LogEntry, Stage},
+    manifest::{find_manifest, ManifestError, ManifestFormat},
+    package::{Command, CommandSet, Package},
+    package_index::{
+        checkout_commit, clone_repo, copy_dir,
+        errors::{GitCloneError, InvalidGitAddress},
+        fetch_tag, fetch_upstream, head_commit, read_remote_manifest, split_version, version_id,
+        GitSource, PackageSource,
+    },
+    service::{self, ServiceError, ServiceRecord},
+    shim::{self, Shim, ShimError},
+    state::{
+        active_version, now, set_active_version, InstallRecord, Revision, SourceRecord, StateError,
+    },
+    vars::{VarError, Vars},
+};
 
-pub static MAIN_PATH: Lazy<PathBuf> =
-    Lazy::new(|| PathBuf::from("~/.run-that/").absolutize().unwrap().into());
-pub static REPOS_PATH: Lazy<PathBuf> = Lazy::new(|| MAIN_PATH.join("repos"));
+/// Default data directory, `RUN_THAT_HOME` or `~/.run-that`
+pub static MAIN_PATH: Lazy<PathBuf> = Lazy::new(|| {
+    main_path(
+        std::env::var_os("RUN_THAT_HOME"),
+        std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")),
+    )
+});
+
+/// `~` is not expanded by anything, so the home directory comes from the environment
+fn main_path(run_that_home: Option<OsString>, home: Option<OsString>) -> PathBuf {
+    run_that_home
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| PathBuf::from(home).join(".run-that")))
+        // Without a home directory, keep the data next to where we're run
+        .unwrap_or_else(|| PathBuf::from(".run-that"))
+        .absolutize()
+        .unwrap()
+        .into()
+}
+
+/// Wait before the first retry of a failed command, doubled for each next one
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Name prefix of staging directories in the repos directory, see `Manager::staging`
+pub(crate) const STAGING_PREFIX: &str = ".tmp";
+/// Where a new version of a package is put inside its staging directory
+const STAGED: &str = "package";
+/// Where the installed version waits in the staging directory until the new one passes init
+const REPLACED: &str = "replaced";
+
+/// Options shared by operations that fetch or execute packages
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    pub quiet: bool,   // Keep our stdout machine readable, see `user_stdout`
+    pub offline: bool, // Resolve git packages from the cache only
+}
 
 #[derive(Error, Debug)]
 pub enum PackageInfoError {
-    #[error("could not read file")]
-    ReadingFileFailed(#[from] std::io::Error),
-    #[error("could not parse file")]
-    ParsingFileFailed(#[from] serde_yaml::Error),
+    #[error("could not read manifest {0:?}")]
+    ReadingFileFailed(PathBuf, #[source] std::io::Error),
+    #[error("could not parse manifest {0:?}")]
+    ParsingFileFailed(PathBuf, #[source] Box<ManifestError>),
+    #[error("could not write manifest {0:?}")]
+    WritingFileFailed(PathBuf, #[source] std::io::Error),
+    #[error("no manifest found in {0:?}")]
+    NoManifest(PathBuf),
+    #[error("invalid manifest {0:?}")]
+    Lint(PathBuf, #[source] VarError),
+}
+
+#[derive(Error, Debug)]
+pub enum ManagerError {
+    #[error("package `{0}` is not installed")]
+    NotInstalled(String, Option<String>), // With the closest installed id, if any is similar
+    #[error("`{0}` matches more installed packages: {1:?}")]
+    Ambiguous(String, Vec<String>),
+    #[error(transparent)]
+    InvalidAddress(#[from] InvalidGitAddress),
+    #[error("could not get `{0}`")]
+    Fetch(String, #[source] GitCloneError), // With the address, url or path of the package
+    #[error(transparent)]
+    PackageInfo(#[from] PackageInfoError),
+    #[error(transparent)]
+    State(#[from] StateError),
+    #[error("could not access {0:?}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("could not link package")]
+    Shim(#[from] ShimError),
+    #[error(transparent)]
+    Service(#[from] ServiceError),
+    #[error("invalid bundle")]
+    Bundle(#[from] BundleError),
+    #[error("checks of `{}` failed", .0.package)]
+    ChecksFailed(CheckReport),
+    #[error("init command `{0}` failed")]
+    InitFailed(Box<Command>),
+    #[error("{0} command `{1}` failed")]
+    HookFailed(&'static str, Box<Command>),
+    #[error("could not start `{0}`: {1}")]
+    RunFailed(Box<Command>, String),
+    #[error("package `{0}` cannot be run on this platform")]
+    Unsupported(String),
+    #[error("`{0}` has no earlier revision `{1}` to roll back to")]
+    NoRevision(String, String),
+    #[error(transparent)]
+    Variable(#[from] VarError),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+}
+
+impl ManagerError {
+    /// For `map_err`, wraps an IO error with the path it happened at
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |e| ManagerError::Io(path, e)
+    }
+
+    /// For `map_err`, wraps an error getting the package from `source`
+    pub(crate) fn fetch<E: Into<GitCloneError>>(source: impl ToString) -> impl FnOnce(E) -> Self {
+        let source = source.to_string();
+        move |e| ManagerError::Fetch(source, e.into())
+    }
+}
+
+/// Result of a single executed command
+#[derive(Debug, Clone, Serialize)]
+pub struct StepResult {
+    pub command: Command,
+    pub success: bool,
+    pub code: Option<i32>,     // Exit code, if the process exited normally
+    pub error: Option<String>, // Why the process could not be started
+    pub timed_out: bool,       // Killed after the command's timeout
+    pub attempts: u32,         // How many times the command was run
+    pub cached: bool,          // Skipped because nothing it depends on changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>, // Process of a detached step, still running
+}
+
+impl StepResult {
+    /// A single attempt that exited normally, `success` and `code` are for the caller to fill in
+    pub fn exited(command: &Command) -> Self {
+        StepResult {
+            command: command.clone(),
+            success: false,
+            code: None,
+            error: None,
+            timed_out: false,
+            attempts: 1,
+            cached: false,
+            pid: None,
+        }
+    }
+
+    pub fn failed(command: &Command, error: impl ToString) -> Self {
+        StepResult {
+            command: command.clone(),
+            success: false,
+            code: None,
+            error: Some(error.to_string()),
+            timed_out: false,
+            attempts: 1,
+            cached: false,
+            pid: None,
+        }
+    }
+
+    fn cached(command: &Command) -> Self {
+        StepResult {
+            command: command.clone(),
+            success: true,
+            code: None,
+            error: None,
+            timed_out: false,
+            attempts: 0,
+            cached: true,
+            pid: None,
+        }
+    }
+
+    /// Whether the step failed and the commands after it should not run
+    pub fn is_fatal(&self) -> bool {
+        !self.success && !self.command.continue_on_error
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    pub package: String,
+    pub passed: bool,
+    pub steps: Vec<StepResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstalledPackage {
+    pub id: String,
+    pub path: PathBuf,
+    pub record: Option<InstallRecord>,
+    pub package: Option<Package>, // `None` if the manifest can't be read
+    pub active: bool,             // Used when the package is named without a version
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageDetails {
+    pub id: String,
+    pub path: PathBuf,
+    pub record: Option<InstallRecord>, // `None` for packages put in place by hand
+    pub package: Package,
+    pub init: Vec<InitStatus>,
+}
+
+/// Manifest of a git package that isn't installed, see [`Manager::preview`]
+#[derive(Debug, Clone, Serialize)]
+pub struct PackagePreview {
+    pub address: String,
+    pub commit: String,
+    pub manifest: String, // File the manifest was read from, like `run.yml`
+    pub package: Package,
+    pub checks: Vec<Command>, // Checks that would run before installing on this platform
+    pub supported: bool,      // Whether the package has a run command for this platform
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallOutcome {
+    pub id: String,
+    pub path: PathBuf,
+    pub installed: bool, // `false` if the package was already installed
+    pub commit: Option<String>,
+    pub checks: Option<CheckReport>,
+    pub init: Vec<StepResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateOutcome {
+    pub id: String,
+    pub changed: bool,
+    pub commit: Option<String>,
+    pub init: Vec<StepResult>,
+    pub post_update: Vec<StepResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RollbackOutcome {
+    pub id: String,
+    pub from: Option<String>, // Commit checked out before the rollback
+    pub to: Revision,
+    pub init: Vec<StepResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoveOutcome {
+    pub id: String,
+    pub shims: Vec<Shim>, // Shims removed together with the package
+    pub uninstall: Vec<StepResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorReport {
+    pub main_path: PathBuf,
+    pub bin_path: PathBuf,
+    pub bin_on_path: bool,
+    pub broken_shims: Vec<Shim>, // Shims of packages that are not installed
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunOutcome {
+    pub id: String,
+    pub command: Command,
+    pub success: bool,
+    pub code: Option<i32>,
+    pub pre_run: Vec<StepResult>,
+    pub post_run: Vec<StepResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceStatus {
+    #[serde(flatten)]
+    pub record: ServiceRecord,
+    pub running: bool,       // `false` if the pid file is stale
+    pub uptime: Option<u64>, // Seconds since the start, if running
+}
+
+impl From<ServiceRecord> for ServiceStatus {
+    fn from(record: ServiceRecord) -> Self {
+        let running = record.is_running();
+        Self {
+            uptime: running.then(|| now().saturating_sub(record.started_at)),
+            running,
+            record,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StopOutcome {
+    pub id: String,
+    pub pid: u32,
+    pub was_running: bool, // `false` if only a stale pid file was removed
+    pub killed: bool,      // SIGKILL was needed after SIGTERM
+}
+
+/// Finds the manifest of a package and parses it.
+///
+/// `path` can be either a manifest file or the package directory, in which case the
+/// manifest is looked up using [`crate::manifest::MANIFEST_LOOKUP`].
 pub fn get_package_info(path: &Path) -> Result<Package, PackageInfoError> {
-    let full_path = if path.is_file() {
-        path.to_path_buf()
+    let (full_path, format) = if path.is_file() {
+        // A manifest named explicitly is YAML unless its extension says otherwise, like `run.yml`
+        let format = ManifestFormat::from_path(path).unwrap_or(ManifestFormat::Yaml);
+        (path.to_path_buf(), format)
     } else {
-        path.join("run.yml")
+        find_manifest(path).ok_or_else(|| PackageInfoError::NoManifest(path.to_path_buf()))?
     };
 
-    let file = std::fs::File::open(full_path)?;
-    let package: Package = serde_yaml::from_reader(file)?;
+    let text = std::fs::read_to_string(&full_path)
+        .map_err(|e| PackageInfoError::ReadingFileFailed(full_path.clone(), e))?;
+    let package = format
+        .parse(&text)
+        .map_err(|e| PackageInfoError::ParsingFileFailed(full_path.clone(), Box::new(e)))?;
+    package
+        .lint()
+        .map_err(|e| PackageInfoError::Lint(full_path, e))?;
     Ok(package)
 }
+
+/// Stdout for commands whose output is shown to the user.
+///
+/// With `quiet` it goes to stderr instead, so our own stdout stays machine readable.
+fn user_stdout(quiet: bool) -> Sink {
+    if quiet {
+        Sink::Stderr
+    } else {
+        Sink::Stdout
+    }
+}
+
+/// Init steps that ran, with fingerprints of the ones that succeeded
+struct InitRun {
+    steps: Vec<StepResult>,
+    fingerprints: Vec<String>,
+}
+
+/// Init step of an installed package and whether it's cached for the current checkout
+#[derive(Debug, Clone, Serialize)]
+pub struct InitStatus {
+    pub command: Command,
+    pub cached: bool,
+}
+
+/// Progress of an operation, passed to the callback set with [`Manager::on_event`]
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    Fetching(&'a PackageSource), // Before a package is cloned, copied or downloaded
+    Updating(&'a str),           // Before an installed package is updated
+    Stage(&'a str, Stage),       // Commands of the package that follow belong to this stage
+    StepStarted(&'a Command),    // Before a command runs, with its variables expanded
+    StepFinished(&'a StepResult),
+    WaitingForLock, // Another process is changing the data directory
+}
+
+type EventCallback = Box<dyn Fn(Event) + Send + Sync>;
+
+/// Installed packages in one data directory and everything that can be done with them.
+///
+/// The data directory holds `repos`, `state`, `cache`, `bin`, `logs`, `services`
+/// and `config.yml`, any of them may be missing.
+pub struct Manager {
+    root: PathBuf,
+    pub(crate) repos: PathBuf,    // Checkouts of installed packages
+    pub(crate) state: PathBuf,    // Install records, see `InstallRecord`
+    pub(crate) cache: PathBuf,    // Mirrors of git remotes
+    pub(crate) bin: PathBuf,      // Shims launching packages
+    pub(crate) logs: PathBuf,     // Logs of executed commands
+    pub(crate) services: PathBuf, // Records of packages running in the background
+    config: Config,
+    pub(crate) opts: Options,
+    on_event: Option<EventCallback>,
+    executor: Arc<dyn Executor>, // Runs every command of packages
+}
+
+impl Manager {
+    /// Manager of the data directory at `root`, reading its config and the environment
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, ManagerError> {
+        let root = root.into();
+        let config = Config::load(&root.join("config.yml"))?.with_env()?;
+        Ok(Self {
+            repos: root.join("repos"),
+            state: root.join("state"),
+            cache: root.join("cache"),
+            bin: root.join("bin"),
+            logs: root.join("logs"),
+            services: root.join("services"),
+            root,
+            config,
+            opts: Options::default(),
+            on_event: None,
+            executor: Arc::new(Local),
+        })
+    }
+
+    /// Manager of [`MAIN_PATH`]
+    pub fn from_env() -> Result<Self, ManagerError> {
+        Self::new(&*MAIN_PATH)
+    }
+
+    pub fn with_options(mut self, opts: Options) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Replaces the config read from the data directory
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets a callback receiving progress of all following operations
+    pub fn on_event(mut self, callback: impl Fn(Event) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Box::new(callback));
+        self
+    }
+
+    /// Runs commands of packages with `executor` instead of as local processes
+    pub fn with_executor(mut self, executor: impl Executor + 'static) -> Self {
+        self.executor = Arc::new(executor);
+        self
+    }
+
+    /// Runs the command once with the executor, recording it in the log
+    fn attempt(
+        &self,
+        command: &Command,
+        dir: &Path,
+        stdout: Sink,
+        stderr: Sink,
+        log: Option<&Log>,
+    ) -> StepResult {
+        if let Some(log) = log {
+            let _ = log.start(command);
+        }
+
+        let start = Instant::now();
+        let step = self.executor.execute(&Step {
+            command,
+            dir,
+            stdout,
+            stderr,
+            log,
+            detached: false,
+        });
+        // Failing to write the log doesn't fail the command
+        if let Some(log) = log {
+            let _ = log.finish(&step, start.elapsed());
+        }
+        step
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(callback) = &self.on_event {
+            callback(event);
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn repos_path(&self) -> &Path {
+        &self.repos
+    }
+
+    pub fn bin_path(&self) -> &Path {
+        &self.bin
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.root.join("config.yml")
+    }
+
+    /// User configuration with environment variables applied, see [`crate::config`]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub(crate) fn cache(&self) -> Cache {
+        Cache::new(&self.cache, self.opts.offline)
+    }
+
+    /// Log of a stage, announced to the event callback
+    pub(crate) fn log(&self, id: &str, stage: Stage) -> Log {
+        self.emit(Event::Stage(id, stage));
+        Log::new(&self.logs, id, stage)
+    }
+}
+
+impl Manager {
+    /// Lists ids of all installed packages, sorted
+    pub fn installed_ids(&self) -> Result<Vec<String>, ManagerError> {
+        list_ids(&self.repos).map_err(ManagerError::io(&self.repos))
+    }
+
+    /// All installed packages, including ones whose manifest can't be read
+    pub fn list(&self) -> Result<Vec<InstalledPackage>, ManagerError> {
+        let ids = self.installed_ids()?;
+        ids.iter()
+            .cloned()
+            .map(|id| {
+                let path = self.repos.join(&id);
+                Ok(InstalledPackage {
+                    record: InstallRecord::load(&self.state, &id)?,
+                    package: get_package_info(&path).ok(),
+                    active: self.active_id(split_version(&id).0, &ids)? == Some(id.clone()),
+                    path,
+                    id,
+                })
+            })
+            .collect()
+    }
+
+    /// Resolves a package name given by the user to an installed package id.
+    ///
+    /// Both the full id (`user/name`) and just the name are accepted, as long as the name is unique.
+    /// A name with a version, like `name@v1`, picks that version, a name without one picks the
+    /// active version, see [`Manager::active_id`].
+    pub fn resolve_id(&self, name: &str) -> Result<String, ManagerError> {
+        let ids = self.installed_ids()?;
+        let name = version_id(name);
+        if ids.contains(&name) && split_version(&name).1.is_some() {
+            return Ok(name);
+        }
+
+        let matching: Vec<_> = if split_version(&name).1.is_some() {
+            ids.iter()
+                .filter(|id| id.rsplit('/').next() == Some(&name))
+                .cloned()
+                .collect()
+        } else {
+            let mut packages: Vec<_> = ids
+                .iter()
+                .map(|id| split_version(id).0)
+                .filter(|p| *p == name || p.rsplit('/').next() == Some(&name))
+                .collect();
+            packages.sort();
+            packages.dedup();
+            match packages[..] {
+                [package] => {
+                    return self.active_id(package, &ids)?.ok_or_else(|| {
+                        let versions = ids.iter().filter(|id| split_version(id).0 == package);
+                        ManagerError::Ambiguous(name.clone(), versions.cloned().collect())
+                    })
+                }
+                _ => packages.into_iter().map(str::to_string).collect(),
+            }
+        };
+
+        match matching.len() {
+            0 => {
+                let suggestion = closest(&name, ids.iter().map(String::as_str))
+                    .or_else(|| closest(&name, ids.iter().filter_map(|id| id.rsplit('/').next())));
+                Err(ManagerError::NotInstalled(
+                    name.to_string(),
+                    suggestion.map(str::to_string),
+                ))
+            }
+            1 => Ok(matching.into_iter().next().unwrap()),
+            _ => Err(ManagerError::Ambiguous(name.to_string(), matching)),
+        }
+    }
+
+    /// Installed version of `package` (`user/name`) used when it's named without a version.
+    ///
+    /// That's the one chosen with [`Manager::activate`], or the one installed without a
+    /// specifier, or the only one installed. `None` if none of them applies.
+    fn active_id(&self, package: &str, ids: &[String]) -> Result<Option<String>, ManagerError> {
+        let versions: Vec<_> = ids
+            .iter()
+            .filter(|id| split_version(id).0 == package)
+            .collect();
+        if let Some(active) = active_version(&self.state, package)? {
+            if versions.contains(&&active) {
+                return Ok(Some(active));
+            }
+        }
+
+        Ok(match versions[..] {
+            [only] => Some(only.clone()),
+            _ => versions
+                .into_iter()
+                .find(|id| id.as_str() == package)
+                .cloned(),
+        })
+    }
+
+    /// Makes the installed version named by `name`, like `tool@v1`, the one used when the
+    /// package is named without a version
+    pub fn activate(&self, name: &str) -> Result<String, ManagerError> {
+        let _lock = self.lock()?;
+        let id = self.resolve_id(name)?;
+        set_active_version(&self.state, split_version(&id).0, Some(&id))?;
+        Ok(id)
+    }
+
+    /// Variables for commands of the package in `dir`
+    fn vars(&self, dir: &Path) -> Vars {
+        Vars::new(dir, &self.root)
+    }
+
+    /// Expands variables in the command and runs it in the package directory.
+    ///
+    /// A failing command is run again up to its `retries`, waiting longer before each attempt.
+    fn execute(
+        &self,
+        command: &Command,
+        vars: &Vars,
+        stdout: Sink,
+        stderr: Sink,
+        log: Option<&Log>,
+    ) -> StepResult {
+        let command = match command.resolve(vars) {
+            Ok(command) => command,
+            Err(e) => {
+                let step = StepResult::failed(command, e);
+                self.emit(Event::StepFinished(&step));
+                return step;
+            }
+        };
+
+        self.emit(Event::StepStarted(&command));
+        let mut delay = RETRY_DELAY;
+        let mut attempts = 1;
+        loop {
+            let step = self.attempt(&command, vars.package_dir(), stdout, stderr, log);
+            if step.success || attempts > command.retries {
+                let step = StepResult { attempts, ..step };
+                self.emit(Event::StepFinished(&step));
+                return step;
+            }
+
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+            attempts += 1;
+        }
+    }
+
+    /// Runs all checks of the package, their output only goes to the log
+    fn run_checks(
+        &self,
+        id: &str,
+        dir: &Path,
+        package: &Package,
+        log: Option<&Log>,
+    ) -> CheckReport {
+        let vars = self.vars(dir);
+        let steps: Vec<_> = package
+            .checks
+            .for_current_target()
+            .map(|c| self.execute(c, &vars, Sink::Null, Sink::Null, log))
+            .collect();
+
+        CheckReport {
+            package: id.to_string(),
+            passed: !steps.iter().any(StepResult::is_fatal),
+            steps,
+        }
+    }
+
+    /// Runs commands of the set for the current platform.
+    ///
+    /// Stops after the first failing one, unless it's allowed to fail with `continue_on_error`.
+    pub(crate) fn run_steps(
+        &self,
+        set: &CommandSet,
+        dir: &Path,
+        quiet: bool,
+        log: Option<&Log>,
+    ) -> Vec<StepResult> {
+        let vars = self.vars(dir);
+        let mut steps = vec![];
+        for command in set.for_current_target() {
+            let step = self.execute(command, &vars, user_stdout(quiet), Sink::Stderr, log);
+            let fatal = step.is_fatal();
+            steps.push(step);
+            if fatal {
+                break;
+            }
+        }
+
+        steps
+    }
+
+    /// Runs init commands of the package, stopping at the first failing one.
+    ///
+    /// Steps whose fingerprint is in `previous` and whose outputs exist are skipped.
+    fn run_init(
+        &self,
+        dir: &Path,
+        package: &Package,
+        previous: &[String],
+        quiet: bool,
+        log: &Log,
+    ) -> Result<InitRun, ManagerError> {
+        let vars = self.vars(dir);
+        let commit = head_commit(dir);
+        let mut run = InitRun {
+            steps: vec![],
+            fingerprints: vec![],
+        };
+
+        for command in package.init.for_current_target() {
+            let resolved = command.resolve(&vars).unwrap_or_else(|_| command.clone());
+            let fingerprint =
+                fingerprint(&resolved, dir, commit.as_deref()).map_err(ManagerError::io(dir))?;
+            if let Some(fingerprint) = fingerprint
+                .as_ref()
+                .filter(|f| previous.contains(f) && outputs_exist(&resolved, dir))
+            {
+                let _ = log.skip(&resolved);
+                run.fingerprints.push(fingerprint.clone());
+                run.steps.push(StepResult::cached(command));
+                continue;
+            }
+
+            let step = self.execute(command, &vars, user_stdout(quiet), Sink::Stderr, Some(log));
+            if step.is_fatal() {
+                return Err(ManagerError::InitFailed(Box::new(step.command)));
+            }
+            if let Some(fingerprint) = fingerprint.filter(|_| step.success) {
+                run.fingerprints.push(fingerprint);
+            }
+            run.steps.push(step);
+        }
+
+        Ok(run)
+    }
+
+    /// Manifest, install record and init cache status of an installed package
+    pub fn info(&self, name: &str) -> Result<PackageDetails, ManagerError> {
+        let id = self.resolve_id(name)?;
+        let path = self.repos.join(&id);
+        Ok(PackageDetails {
+            package: get_package_info(&path)?,
+            record: InstallRecord::load(&self.state, &id)?,
+            init: self.init_status(&id)?,
+            path,
+            id,
+        })
+    }
+
+    /// Reads the manifest of a git package at its specifier from the mirror, without installing it
+    pub fn preview(&self, source: &GitSource) -> Result<PackagePreview, ManagerError> {
+        let address = source.to_string();
+        let Some(manifest) =
+            read_remote_manifest(source, &self.cache()).map_err(ManagerError::fetch(&address))?
+        else {
+            return Err(PackageInfoError::NoManifest(PathBuf::from(&address)).into());
+        };
+
+        // Not a real path, but tells where the broken manifest is
+        let path = PathBuf::from(format!("{address}/{}", manifest.file));
+        let package = manifest
+            .format
+            .parse(&manifest.text)
+            .map_err(|e| PackageInfoError::ParsingFileFailed(path.clone(), Box::new(e)))?;
+        package
+            .lint()
+            .map_err(|e| PackageInfoError::Lint(path, e))?;
+
+        Ok(PackagePreview {
+            address,
+            commit: manifest.commit,
+            manifest: manifest.file.to_string(),
+            checks: package.checks.for_current_target().cloned().collect(),
+            supported: package.run.for_current_target().is_some(),
+            package,
+        })
+    }
+
+    /// Which init steps of an installed package are cached, see [`crate::fingerprint`]
+    pub fn init_status(&self, id: &str) -> Result<Vec<InitStatus>, ManagerError> {
+        let path = self.repos.join(id);
+        let package = get_package_info(&path)?;
+        let previous = InstallRecord::load(&self.state, id)?
+            .map(|r| r.init_fingerprints)
+            .unwrap_or_default();
+        let vars = self.vars(&path);
+        let commit = head_commit(&path);
+
+        let mut status = vec![];
+        for command in package.init.for_current_target() {
+            let resolved = command.resolve(&vars).unwrap_or_else(|_| command.clone());
+            let cached = fingerprint(&resolved, &path, commit.as_deref())
+                .map_err(ManagerError::io(&path))?
+                .is_some_and(|f| previous.contains(&f) && outputs_exist(&resolved, &path));
+            status.push(InitStatus {
+                command: command.clone(),
+                cached,
+            });
+        }
+
+        Ok(status)
+    }
+
+    /// Runs commands of a lifecycle hook like `pre_run`, failing with the first failing one
+    fn run_hook(
+        &self,
+        hook: &'static str,
+        set: &CommandSet,
+        dir: &Path,
+        quiet: bool,
+        log: &Log,
+    ) -> Result<Vec<StepResult>, ManagerError> {
+        let steps = self.run_steps(set, dir, quiet, Some(log));
+        match steps.last() {
+            Some(step) if step.is_fatal() => Err(ManagerError::HookFailed(
+                hook,
+                Box::new(step.command.clone()),
+            )),
+            _ => Ok(steps),
+        }
+    }
+
+    /// Runs checks of a package that doesn't have to be installed
+    pub fn check(&self, path: &Path) -> Result<CheckReport, ManagerError> {
+        let package = get_package_info(path)?;
+        let dir = if path.is_file() {
+            path.parent().unwrap_or(Path::new("."))
+        } else {
+            path
+        };
+
+        Ok(self.run_checks(&package.name, dir, &package, None))
+    }
+
+    pub fn install(
+        &self,
+        source: &PackageSource,
+        force: bool,
+    ) -> Result<InstallOutcome, ManagerError> {
+        let _lock = self.lock()?;
+        let id = source.id();
+        let path = self.repos.join(&id);
+        if path.exists() && !force {
+            return Ok(InstallOutcome {
+                commit: head_commit(&path),
+                id,
+                path,
+                installed: false,
+                checks: None,
+                init: vec![],
+            });
+        }
+
+        self.emit(Event::Fetching(source));
+        let staging = self.staging()?;
+        let dir = staging.path().join(STAGED);
+        // A version range is resolved once, so the checkout and the record agree on the tag
+        let resolved = match source.git() {
+            Some(git) => git
+                .resolve(&self.cache())
+                .map_err(ManagerError::fetch(git))?,
+            None => None,
+        };
+        match &resolved {
+            Some((git, _)) => {
+                clone_repo(git, &dir, &self.cache()).map_err(ManagerError::fetch(git))?
+            }
+            None => source
+                .put_to(&dir, &self.cache())
+                .map_err(ManagerError::fetch(source))?,
+        }
+        self.finish_install(id, staging, source.into(), resolved.map(|(_, tag)| tag))
+    }
+
+    /// Runs checks of a package put into `staging`, moves it into place, runs its init and saves
+    /// its install record.
+    ///
+    /// If any of them fails, the new package is dropped and the installed version, if any, is kept,
+    /// see [`Manager::swap_and_init`].
+    fn finish_install(
+        &self,
+        id: String,
+        staging: TempDir,
+        source: SourceRecord,
+        tag: Option<String>,
+    ) -> Result<InstallOutcome, ManagerError> {
+        let dir = staging.path().join(STAGED);
+        let package = get_package_info(&dir)?;
+        let log = self.log(&id, Stage::Check);
+        let checks = self.run_checks(&id, &dir, &package, Some(&log));
+        if !checks.passed {
+            return Err(ManagerError::ChecksFailed(checks));
+        }
+
+        // Git packages are compared by commit, copies by their files
+        let tree = match source {
+            SourceRecord::Git(_) => None,
+            _ => Some(fingerprint::tree(&dir).map_err(ManagerError::io(&dir))?),
+        };
+        let log = self.log(&id, Stage::Init);
+        let init = self.swap_and_init(&id, staging, &package, &[], &log)?;
+
+        let commit = head_commit(self.repos.join(&id));
+        let mut record = InstallRecord::new(id.clone(), source, commit.clone());
+        record.tag = tag;
+        record.tree = tree;
+        record.init_fingerprints = init.fingerprints;
+        // A reinstall continues the history of the replaced version
+        if let Some(replaced) = InstallRecord::load(&self.state, &id)? {
+            record.history = replaced.history;
+        }
+        record.push_revision();
+        record.save(&self.state)?;
+
+        Ok(InstallOutcome {
+            path: self.repos.join(&id),
+            id,
+            installed: true,
+            commit,
+            checks: Some(checks),
+            init: init.steps,
+        })
+    }
+
+    /// Packs an installed package into a bundle at `output`, see [`crate::bundle`]
+    pub fn export(&self, name: &str, output: &Path) -> Result<BundleInfo, ManagerError> {
+        let id = self.resolve_id(name)?;
+        let path = self.repos.join(&id);
+        let record = match InstallRecord::load(&self.state, &id)? {
+            Some(record) => record,
+            None => InstallRecord::new(
+                id.clone(),
+                SourceRecord::Local(path.clone()),
+                head_commit(&path),
+            ),
+        };
+
+        Ok(bundle::pack(&path, record, output)?)
+    }
+
+    /// Installs a package from a bundle made by [`Manager::export`], like [`Manager::install`] would
+    pub fn import(&self, bundle: &Path, force: bool) -> Result<InstallOutcome, ManagerError> {
+        let _lock = self.lock()?;
+        let info = bundle::read_info(bundle)?;
+        let id = info.record.id;
+        let path = self.repos.join(&id);
+        if path.exists() && !force {
+            return Ok(InstallOutcome {
+                commit: head_commit(&path),
+                id,
+                path,
+                installed: false,
+                checks: None,
+                init: vec![],
+            });
+        }
+
+        let staging = self.staging()?;
+        bundle::unpack(bundle, &staging.path().join(STAGED))?;
+        self.finish_install(id, staging, info.record.source, info.record.tag)
+    }
+
+    /// Locks the data directory until the guard is dropped, so that processes don't
+    /// install, update or remove packages at the same time
+    pub(crate) fn lock(&self) -> Result<FileLock, ManagerError> {
+        let path = self.root.join("lock");
+        FileLock::acquire(&path, || self.emit(Event::WaitingForLock))
+            .map_err(ManagerError::io(path))
+    }
+
+    /// Empty hidden directory next to the checkouts, where a new version of a package is
+    /// put into [`STAGED`] before it replaces the installed one
+    fn staging(&self) -> Result<TempDir, ManagerError> {
+        std::fs::create_dir_all(&self.repos)
+            .and_then(|_| {
+                tempfile::Builder::new()
+                    .prefix(STAGING_PREFIX)
+                    .tempdir_in(&self.repos)
+            })
+            .map_err(ManagerError::io(&self.repos))
+    }
+
+    /// Moves the package staged in `staging` into place and runs its init there, so paths that
+    /// init bakes into the package, like `${PACKAGE_DIR}` or a virtualenv, point to where it stays.
+    ///
+    /// The replaced version waits in `staging` and goes away with it. If init fails, the new
+    /// version is dropped instead and the replaced one is moved back.
+    fn swap_and_init(
+        &self,
+        id: &str,
+        staging: TempDir,
+        package: &Package,
+        previous: &[String],
+        log: &Log,
+    ) -> Result<InitRun, ManagerError> {
+        let path = self.repos.join(id);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(ManagerError::io(parent))?;
+        }
+        if path.exists() {
+            std::fs::rename(&path, staging.path().join(REPLACED))
+                .map_err(ManagerError::io(&path))?;
+        }
+        std::fs::rename(staging.path().join(STAGED), &path).map_err(ManagerError::io(&path))?;
+
+        match self.run_init(&path, package, previous, self.opts.quiet, log) {
+            Ok(init) => Ok(init),
+            Err(e) => {
+                std::fs::rename(&path, staging.path().join(STAGED))
+                    .map_err(ManagerError::io(&path))?;
+                let replaced = staging.path().join(REPLACED);
+                if replaced.exists() {
+                    std::fs::rename(replaced, &path).map_err(ManagerError::io(&path))?;
+                } else {
+                    self.remove_checkout(id)?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Stages a copy of the git checkout at `path` with `commit` checked out, so it can replace
+    /// the checkout once its init passes. `None` if the checkout is at `commit` already.
+    fn stage_commit(&self, path: &Path, commit: &str) -> Result<Option<TempDir>, ManagerError> {
+        if head_commit(path).as_deref() == Some(commit) {
+            return Ok(None);
+        }
+
+        // Copied with the build outputs, so cached init steps stay cached
+        let staging = self.staging()?;
+        let dir = staging.path().join(STAGED);
+        copy_dir(path, &dir).map_err(ManagerError::io(path))?;
+        checkout_commit(&dir, commit).map_err(ManagerError::fetch(commit))?;
+        Ok(Some(staging))
+    }
+
+    fn remove_checkout(&self, id: &str) -> Result<(), ManagerError> {
+        let path = self.repos.join(id);
+        if path.exists() {
+            std::fs::remove_dir_all(&path).map_err(ManagerError::io(&path))?;
+        }
+
+        // Clean up the user directory if this was its last package
+        if let Some(user) = path.parent() {
+            if user
+                .read_dir()
+                .map_err(ManagerError::io(user))?
+                .next()
+                .is_none()
+            {
+                std::fs::remove_dir(user).map_err(ManagerError::io(user))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `uninstall` commands of the package and removes it with its shims.
+    ///
+    /// `name` is resolved with [`Manager::resolve_id`]. A failing `uninstall` command aborts
+    /// the removal, unless `force` is set.
+    pub fn remove(&self, name: &str, force: bool) -> Result<RemoveOutcome, ManagerError> {
+        let _lock = self.lock()?;
+        let id = self.resolve_id(name)?;
+        let path = self.repos.join(&id);
+        let uninstall = match get_package_info(&path) {
+            Ok(package) => {
+                let log = self.log(&id, Stage::Uninstall);
+                let steps = self.run_steps(&package.uninstall, &path, self.opts.quiet, Some(&log));
+                match steps.last() {
+                    Some(step) if step.is_fatal() && !force => {
+                        let command = Box::new(step.command.clone());
+                        return Err(ManagerError::HookFailed("uninstall", command));
+                    }
+                    _ => steps,
+                }
+            }
+            Err(_) if force => vec![],
+            Err(e) => return Err(e.into()),
+        };
+
+        self.remove_checkout(&id)?;
+        InstallRecord::delete(&self.state, &id)?;
+        let package = split_version(&id).0;
+        if active_version(&self.state, package)?.as_deref() == Some(id.as_str()) {
+            set_active_version(&self.state, package, None)?;
+        }
+        let shims = shim::remove_for(&self.bin, &id).map_err(ManagerError::io(&self.bin))?;
+        Ok(RemoveOutcome {
+            id,
+            shims,
+            uninstall,
+        })
+    }
+
+    /// Creates a shim in the `bin` directory launching the package, named after it unless `as_name` is given
+    pub fn link(
+        &self,
+        name: &str,
+        as_name: Option<&str>,
+        force: bool,
+    ) -> Result<Shim, ManagerError> {
+        let id = self.resolve_id(name)?;
+        let package = get_package_info(&self.repos.join(&id))?;
+        if package.run.for_current_target().is_none() {
+            return Err(ManagerError::Unsupported(id));
+        }
+
+        let package = split_version(&id).0;
+        let shim_name = as_name.unwrap_or_else(|| package.rsplit('/').next().unwrap_or(package));
+        let exe = std::env::current_exe().map_err(ShimError::from)?;
+        Ok(shim::create(
+            &self.bin, shim_name, &id, &exe, &self.root, force,
+        )?)
+    }
+
+    pub fn doctor(&self) -> Result<DoctorReport, ManagerError> {
+        let ids = self.installed_ids()?;
+        let broken_shims = shim::list(&self.bin)
+            .map_err(ManagerError::io(&self.bin))?
+            .into_iter()
+            .filter(|s| !ids.contains(&s.id))
+            .collect();
+
+        Ok(DoctorReport {
+            main_path: self.root.clone(),
+            bin_path: self.bin.clone(),
+            bin_on_path: shim::on_path(&self.bin),
+            broken_shims,
+        })
+    }
+
+    /// Updates an installed package to the newest revision and reruns init if it changed.
+    ///
+    /// Init steps that don't depend on the changes are skipped, unless `force_init` is set,
+    /// which also reruns init when nothing changed.
+    pub fn update(&self, name: &str, force_init: bool) -> Result<UpdateOutcome, ManagerError> {
+        let _lock = self.lock()?;
+        let id = self.resolve_id(name)?;
+        let path = self.repos.join(&id);
+        let mut record = InstallRecord::load(&self.state, &id)?;
+        self.emit(Event::Updating(&id));
+
+        // The new version is staged and replaces the installed one once its init passes
+        let staging = match record.as_ref().map(|r| &r.source) {
+            Some(SourceRecord::Local(source)) => {
+                let staging = self.staging()?;
+                PackageSource::new_local(source)
+                    .put_to(&staging.path().join(STAGED), &self.cache())
+                    .map_err(ManagerError::fetch(source.display()))?;
+                self.changed_copy(staging, &mut record)?
+            }
+            Some(SourceRecord::Archive { location, sha256 }) => {
+                let staging = self.staging()?;
+                PackageSource::new_archive(location, sha256.clone())
+                    .ok_or_else(|| ArchiveError::UnknownKind(location.clone()).into())
+                    .and_then(|source| source.put_to(&staging.path().join(STAGED), &self.cache()))
+                    .map_err(ManagerError::fetch(location))?;
+                self.changed_copy(staging, &mut record)?
+            }
+            Some(SourceRecord::Git(address)) => {
+                let source = GitSource::new(address.clone(), &self.config)?;
+                // Packages installed with a version range move to the newest tag in it
+                let fetch = || ManagerError::fetch(&source);
+                let target = match source.resolve(&self.cache()).map_err(fetch())? {
+                    Some((_, tag)) => {
+                        let commit =
+                            fetch_tag(&path, &self.cache(), &source.url(), &tag).map_err(fetch())?;
+                        if let Some(record) = &mut record {
+                            record.tag = Some(tag);
+                        }
+                        Some(commit)
+                    }
+                    None => {
+                        fetch_upstream(&path, &self.cache(), Some(&source.url())).map_err(fetch())?
+                    }
+                };
+                match target {
+                    Some(commit) => self.stage_commit(&path, &commit)?,
+                    None => None,
+                }
+            }
+            None => match fetch_upstream(&path, &self.cache(), None)
+                .map_err(ManagerError::fetch(path.display()))?
+            {
+                Some(commit) => self.stage_commit(&path, &commit)?,
+                None => None,
+            },
+        };
+        let changed = staging.is_some();
+
+        let dir = match &staging {
+            Some(staging) => staging.path().join(STAGED),
+            None => path.clone(),
+        };
+        let package = (changed || force_init)
+            .then(|| get_package_info(&dir))
+            .transpose()?;
+        let log = package.as_ref().map(|_| self.log(&id, Stage::Init));
+        let previous = match &record {
+            Some(record) if !force_init => record.init_fingerprints.as_slice(),
+            _ => &[],
+        };
+        let init = match (&package, &log, staging) {
+            (Some(package), Some(log), Some(staging)) => {
+                Some(self.swap_and_init(&id, staging, package, previous, log)?)
+            }
+            (Some(package), Some(log), None) => {
+                Some(self.run_init(&path, package, previous, self.opts.quiet, log)?)
+            }
+            _ => None,
+        };
+
+        let commit = head_commit(&path);
+        if let Some(record) = &mut record {
+            record.commit = commit.clone();
+            record.updated_at = now();
+            if let Some(init) = &init {
+                record.init_fingerprints = init.fingerprints.clone();
+            }
+            record.push_revision();
+            record.save(&self.state)?;
+        }
+
+        // The update is done at this point, a failure here only needs the hook fixed
+        let post_update = match &package {
+            Some(package) if changed => self.run_hook(
+                "post_update",
+                &package.post_update,
+                &path,
+                self.opts.quiet,
+                &self.log(&id, Stage::PostUpdate),
+            )?,
+            _ => vec![],
+        };
+
+        Ok(UpdateOutcome {
+            id,
+            changed,
+            commit,
+            init: init.map(|i| i.steps).unwrap_or_default(),
+            post_update,
+        })
+    }
+
+    /// The staged copy of a local or archive package, `None` if its files are the installed ones.
+    ///
+    /// The fingerprint of a changed copy is put into `record`, to be saved once it's installed.
+    fn changed_copy(
+        &self,
+        staging: TempDir,
+        record: &mut Option<InstallRecord>,
+    ) -> Result<Option<TempDir>, ManagerError> {
+        let dir = staging.path().join(STAGED);
+        let tree = fingerprint::tree(&dir).map_err(ManagerError::io(&dir))?;
+        match record {
+            Some(record) if record.tree.as_ref() == Some(&tree) => Ok(None),
+            Some(record) => {
+                record.tree = Some(tree);
+                Ok(Some(staging))
+            }
+            None => Ok(Some(staging)),
+        }
+    }
+
+    /// Checks out an earlier revision of a git package and reruns its init.
+    ///
+    /// `to` is a commit from the history, or how many revisions to go back, the previous one
+    /// if not given. Only init steps whose `inputs` are unchanged from the current checkout
+    /// are skipped, the outputs on disk are its own. The rollback is added to the history, so
+    /// rolling back again returns to the version rolled back from.
+    pub fn rollback(&self, name: &str, to: Option<&str>) -> Result<RollbackOutcome, ManagerError> {
+        let _lock = self.lock()?;
+        let id = self.resolve_id(name)?;
+        let path = self.repos.join(&id);
+        let Some(mut record) = InstallRecord::load(&self.state, &id)? else {
+            return Err(ManagerError::NoRevision(id, to.unwrap_or("1").to_string()));
+        };
+
+        let current = record.commit.clone();
+        let earlier: Vec<_> = record
+            .history
+            .iter()
+            .rev()
+            .filter(|r| Some(&r.commit) != current.as_ref())
+            .collect();
+        let target = match to {
+            None => earlier.first(),
+            // Commits are at least 4 hex digits, like in addresses
+            Some(n) if n.len() < 4 && n.bytes().all(|b| b.is_ascii_digit()) => n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| earlier.get(n.checked_sub(1)?)),
+            Some(commit) => earlier.iter().find(|r| r.commit.starts_with(commit)),
+        };
+        let Some(target) = target.map(|r| (*r).clone()) else {
+            return Err(ManagerError::NoRevision(id, to.unwrap_or("1").to_string()));
+        };
+
+        self.emit(Event::Updating(&id));
+        let log = self.log(&id, Stage::Init);
+        let previous = &record.init_fingerprints;
+        let init = match self.stage_commit(&path, &target.commit)? {
+            Some(staging) => {
+                let package = get_package_info(&staging.path().join(STAGED))?;
+                self.swap_and_init(&id, staging, &package, previous, &log)?
+            }
+            None => {
+                let package = get_package_info(&path)?;
+                self.run_init(&path, &package, previous, self.opts.quiet, &log)?
+            }
+        };
+
+        record.commit = Some(target.commit.clone());
+        record.tag = target.tag.clone();
+        record.updated_at = now();
+        record.init_fingerprints = init.fingerprints;
+        record.push_revision();
+        record.save(&self.state)?;
+
+        Ok(RollbackOutcome {
+            id,
+            from: current,
+            to: target,
+            init: init.steps,
+        })
+    }
+
+    /// Resolved run command of an installed package, with `args` given by the user appended
+    fn run_command(
+        &self,
+        id: &str,
+        package: &Package,
+        args: &[String],
+        params: BTreeMap<String, String>,
+    ) -> Result<Command, ManagerError> {
+        let path = self.repos.join(id);
+
+        // Arguments given by the user are passed as they are
+        let mut command = package
+            .run
+            .for_current_target()
+            .ok_or_else(|| ManagerError::Unsupported(id.to_string()))?
+            .resolve(&self.vars(&path).with_params(params))?;
+        command.args.extend_from_slice(args);
+        Ok(command)
+    }
+
+    /// Runs an installed package with additional arguments, `params` fill in `${param:NAME}`
+    pub fn run(
+        &self,
+        name: &str,
+        args: &[String],
+        params: BTreeMap<String, String>,
+    ) -> Result<RunOutcome, ManagerError> {
+        let id = self.resolve_id(name)?;
+        let path = self.repos.join(&id);
+        let package = get_package_info(&path)?;
+        let command = self.run_command(&id, &package, args, params)?;
+
+        let log = self.log(&id, Stage::Run);
+        let pre_run = self.run_hook("pre_run", &package.pre_run, &path, self.opts.quiet, &log)?;
+
+        // Output is shown as it comes and copied into the log, stdin stays the terminal
+        let step = self.attempt(
+            &command,
+            &path,
+            user_stdout(self.opts.quiet),
+            Sink::Stderr,
+            Some(&log),
+        );
+        if let Some(error) = step.error {
+            return Err(ManagerError::RunFailed(Box::new(command), error));
+        }
+
+        // Runs even if the program failed, its exit code is what we report
+        let post_run = self.run_steps(&package.post_run, &path, self.opts.quiet, Some(&log));
+
+        Ok(RunOutcome {
+            id,
+            command,
+            success: step.success,
+            code: step.code,
+            pre_run,
+            post_run,
+        })
+    }
+
+    /// Runs an installed package in the background, its output goes to a `service` log
+    pub fn start(
+        &self,
+        name: &str,
+        args: &[String],
+        params: BTreeMap<String, String>,
+    ) -> Result<ServiceStatus, ManagerError> {
+        let id = self.resolve_id(name)?;
+        if let Some(record) = ServiceRecord::load(&self.services, &id)? {
+            if record.is_running() {
+                return Err(ServiceError::AlreadyRunning(id, record.pid).into());
+            }
+        }
+
+        let path = self.repos.join(&id);
+        let package = get_package_info(&path)?;
+        let command = self.run_command(&id, &package, args, params.clone())?;
+
+        let log = self.log(&id, Stage::Service);
+        self.run_hook("pre_run", &package.pre_run, &path, true, &log)?;
+        let _ = log.start(&command);
+        let step = self.executor.execute(&Step {
+            command: &command,
+            dir: &path,
+            stdout: Sink::Null,
+            stderr: Sink::Null,
+            log: Some(&log),
+            detached: true,
+        });
+        let Some(pid) = step.pid else {
+            let error = step
+                .error
+                .unwrap_or_else(|| "not started in the background".into());
+            return Err(ManagerError::RunFailed(Box::new(command), error));
+        };
+
+        let record = ServiceRecord {
+            id,
+            pid,
+            started_at: now(),
+            start_time: service::start_time(pid),
+            command,
+            args: args.to_vec(),
+            params,
+            log: log.path().to_path_buf(),
+        };
+        record.save(&self.services)?;
+        Ok(record.into())
+    }
+
+    /// Id of a package with a service record, which doesn't have to be installed anymore
+    fn resolve_service(&self, name: &str) -> Result<String, ManagerError> {
+        match self.resolve_id(name) {
+            Ok(id) => Ok(id),
+            Err(ManagerError::NotInstalled(..))
+                if ServiceRecord::path(&self.services, name).exists() =>
+            {
+                Ok(name.to_string())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stops a package running in the background, killing it if it doesn't stop in time
+    pub fn stop(&self, name: &str) -> Result<StopOutcome, ManagerError> {
+        let id = self.resolve_service(name)?;
+        let record = ServiceRecord::load(&self.services, &id)?
+            .ok_or_else(|| ServiceError::NotRunning(id.clone()))?;
+
+        let was_running = record.is_running();
+        let killed = was_running
+            && service::terminate(record.pid, record.start_time, service::STOP_TIMEOUT)?;
+        ServiceRecord::delete(&self.services, &id)?;
+
+        Ok(StopOutcome {
+            id,
+            pid: record.pid,
+            was_running,
+            killed,
+        })
+    }
+
+    /// Stops the package if it's running and starts it again with the same arguments
+    pub fn restart(&self, name: &str) -> Result<ServiceStatus, ManagerError> {
+        let id = self.resolve_id(name)?;
+        let (args, params) = match ServiceRecord::load(&self.services, &id)? {
+            Some(record) => {
+                self.stop(&id)?;
+                (record.args, record.params)
+            }
+            None => Default::default(),
+        };
+
+        self.start(&id, &args, params)
+    }
+
+    /// Status of packages running in the background, or of just one
+    pub fn status(&self, name: Option<&str>) -> Result<Vec<ServiceStatus>, ManagerError> {
+        let records = match name {
+            Some(name) => {
+                let id = self.resolve_service(name)?;
+                ServiceRecord::load(&self.services, &id)?
+                    .into_iter()
+                    .collect()
+            }
+            None => ServiceRecord::list(&self.services)?,
+        };
+
+        Ok(records.into_iter().map(ServiceStatus::from).collect())
+    }
+
+    /// Logs of a package, oldest first.
+    ///
+    /// Packages whose install failed still have logs, so their id is accepted as well.
+    pub fn logs(
+        &self,
+        name: &str,
+        stage: Option<Stage>,
+    ) -> Result<(String, Vec<LogEntry>), ManagerError> {
+        let id = match self.resolve_id(name) {
+            Ok(id) => id,
+            Err(ManagerError::NotInstalled(..)) if self.logs.join(name).is_dir() => {
+                name.to_string()
+            }
+            Err(e) => return Err(e),
+        };
+
+        let dir = self.logs.join(&id);
+        let logs = logs::list(&dir, stage).map_err(ManagerError::io(&dir))?;
+        Ok((id, logs))
+    }
+
+    /// Urls of remotes used by installed git packages
+    pub(crate) fn used_remotes(&self) -> Result<Vec<String>, ManagerError> {
+        let mut urls = vec![];
+        for id in self.installed_ids()? {
+            if let Some(InstallRecord {
+                source: SourceRecord::Git(address),
+                ..
+            }) = InstallRecord::load(&self.state, &id)?
+            {
+                if let Some(git) = PackageSource::new_git(address, &self.config)?.git() {
+                    urls.push(git.url());
+                }
+            }
+        }
+
+        Ok(urls)
+    }
+
+    pub fn cache_list(&self) -> Result<Vec<MirrorInfo>, ManagerError> {
+        self.cache().list().map_err(ManagerError::io(&self.cache))
+    }
+
+    /// Removes mirrors not used by any installed package
+    pub fn cache_prune(&self) -> Result<Vec<MirrorInfo>, ManagerError> {
+        let _lock = self.lock()?;
+        let used = self.used_remotes()?;
+        self.cache()
+            .prune(&used)
+            .map_err(ManagerError::io(&self.cache))
+    }
+}
+/// Package directories in `repos`, as `user/name`
+fn list_ids(repos: &Path) -> Result<Vec<String>, std::io::Error> {
+    let mut ids = vec![];
+    if !repos.exists() {
+        return Ok(ids);
+    }
+
+    for user in std::fs::read_dir(repos)? {
+        let user = user?;
+        // Hidden directories are unfinished installs
+        if !user.file_type()?.is_dir() || user.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        for name in std::fs::read_dir(user.path())? {
+            let name = name?;
+            if name.file_type()?.is_dir() {
+                ids.push(format!(
+                    "{}/{}",
+                    user.file_name().to_string_lossy(),
+                    name.file_name().to_string_lossy()
+                ));
+            }
+        }
+    }
+
+    ids.sort();
+    Ok(ids)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// Runs commands locally with their output only in the log, not in the test output
+    struct Silent;
+
+    impl Executor for Silent {
+        fn execute(&self, step: &Step) -> StepResult {
+            Local.execute(&Step {
+                stdout: Sink::Null,
+                stderr: Sink::Null,
+                ..*step
+            })
+        }
+    }
+
+    fn sh(script: &str) -> Command {
+        Command {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn manifest_file_with_other_extension() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("launcher.conf");
+        std::fs::write(
+            &path,
+            "name: Tool\ndescription: test\nauthors: []\nrun:\n  default:\n    program: tool\n",
+        )
+        .unwrap();
+        assert_eq!(get_package_info(&path).unwrap().name, "Tool");
+
+        std::fs::write(&path, "{ not yaml").unwrap();
+        assert!(matches!(
+            get_package_info(&path),
+            Err(PackageInfoError::ParsingFileFailed(..))
+        ));
+    }
+
+    #[test]
+    fn step_options() {
+        let tmp = tempfile::tempdir().unwrap();
+        let set = CommandSet {
+            global: vec![
+                Command {
+                    success_codes: vec![1],
+                    ..sh("exit 1")
+                },
+                Command {
+                    continue_on_error: true,
+                    ..sh("exit 2")
+                },
+                Command {
+                    timeout: Some(1),
+                    ..sh("sleep 10")
+                },
+                sh("echo unreachable"),
+            ],
+            ..Default::default()
+        };
+
+        let manager = Manager::new(tmp.path()).unwrap();
+        let steps = manager.run_steps(&set, tmp.path(), true, None);
+        assert_eq!(steps.len(), 3);
+        assert!(steps[0].success);
+        assert!(!steps[1].success && !steps[1].is_fatal());
+        assert!(steps[2].timed_out && steps[2].is_fatal());
+    }
+
+    #[test]
+    fn retries() {
+        let tmp = tempfile::tempdir().unwrap();
+        // Fails the first time only
+        let flaky = Command {
+            retries: 2,
+            ..sh("test -e tried || { touch tried; exit 1; }")
+        };
+
+        let manager = Manager::new(tmp.path()).unwrap();
+        let vars = manager.vars(tmp.path());
+        let step = manager.execute(&flaky, &vars, Sink::Null, Sink::Null, None);
+        assert!(step.success);
+        assert_eq!(step.attempts, 2);
+    }
+
+    #[test]
+    fn embedded() {
+        use std::sync::{Arc, Mutex};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("tool");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(
+            source.join("run.yml"),
+            "name: Tool\ndescription: test\nauthors: []\n\
+             init:\n  global:\n    - program: sh\n      args: [-c, touch built]\n\
+             run:\n  default:\n    program: test\n    args: [-e, built]\n",
+        )
+        .unwrap();
+
+        let stages = Arc::new(Mutex::new(vec![]));
+        let seen = stages.clone();
+        let manager = Manager::new(tmp.path().join("home"))
+            .unwrap()
+            .with_options(Options {
+                quiet: true,
+                offline: true,
+            })
+            .on_event(move |event| {
+                if let Event::Stage(_, stage) = event {
+                    seen.lock().unwrap().push(stage.name());
+                }
+            });
+
+        let outcome = manager
+            .install(&PackageSource::new_local(&source), false)
+            .unwrap();
+        assert_eq!(outcome.id, "_local/tool");
+        assert_eq!(manager.list().unwrap().len(), 1);
+        assert_eq!(manager.info("tool").unwrap().package.name, "Tool");
+        assert!(manager.run("tool", &[], BTreeMap::new()).unwrap().success);
+        assert_eq!(*stages.lock().unwrap(), ["check", "init", "run"]);
+
+        manager.remove("tool", false).unwrap();
+        assert!(matches!(
+            manager.resolve_id("tool"),
+            Err(ManagerError::NotInstalled(..))
+        ));
+    }
+
+    #[test]
+    fn mocked_init() {
+        use crate::executor::Mock;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("tool");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(
+            source.join("run.yml"),
+            "name: Tool\ndescription: test\nauthors: []\n\
+             init:\n  global:\n    - program: make\n      args: [all]\n\
+             run:\n  default:\n    program: tool\n",
+        )
+        .unwrap();
+
+        let mock = Arc::new(Mock::new().exit_with("make", 2));
+        let manager = Manager::new(tmp.path().join("home"))
+            .unwrap()
+            .with_executor(mock.clone());
+
+        assert!(matches!(
+            manager.install(&PackageSource::new_local(&source), false),
+            Err(ManagerError::InitFailed(_))
+        ));
+        assert_eq!(mock.calls()[0].args, ["all"]);
+        assert!(!manager.repos.join("_local/tool").exists());
+    }
+
+    #[test]
+    fn service_through_executor() {
+        use crate::executor::Mock;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("server");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(
+            source.join("run.yml"),
+            "name: Server\ndescription: test\nauthors: []\nrun:\n  default:\n    program: serve\n",
+        )
+        .unwrap();
+
+        // The mock starts nothing, so there is no process to record
+        let mock = Arc::new(Mock::new());
+        let manager = Manager::new(tmp.path().join("home"))
+            .unwrap()
+            .with_executor(mock.clone());
+        manager
+            .install(&PackageSource::new_local(&source), false)
+            .unwrap();
+        assert!(matches!(
+            manager.start("server", &[], BTreeMap::new()),
+            Err(ManagerError::RunFailed(..))
+        ));
+        assert_eq!(mock.calls().last().unwrap().program, "serve");
+        assert!(manager.status(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn failing_hooks_abort() {
+        use crate::executor::Mock;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("tool");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(
+            source.join("run.yml"),
+            "name: Tool\ndescription: test\nauthors: []\n\
+             pre_run:\n  global:\n    - program: migrate\n\
+             uninstall:\n  global:\n    - program: cleanup\n\
+             run:\n  default:\n    program: tool\n",
+        )
+        .unwrap();
+
+        let mock = Arc::new(Mock::new().exit_with("migrate", 1).exit_with("cleanup", 1));
+        let manager = Manager::new(tmp.path().join("home"))
+            .unwrap()
+            .with_executor(mock.clone());
+        manager
+            .install(&PackageSource::new_local(&source), false)
+            .unwrap();
+
+        assert!(matches!(
+            manager.run("tool", &[], BTreeMap::new()),
+            Err(ManagerError::HookFailed("pre_run", _))
+        ));
+        assert!(mock.calls().iter().all(|c| c.program != "tool"));
+
+        assert!(matches!(
+            manager.remove("tool", false),
+            Err(ManagerError::HookFailed("uninstall", _))
+        ));
+        assert!(manager.repos.join("_local/tool").exists());
+        manager.remove("tool", true).unwrap();
+        assert!(!manager.repos.join("_local/tool").exists());
+    }
+
+    #[test]
+    fn post_update_only_on_change() {
+        use crate::test_support::{commit, Remotes};
+
+        let hooked = |name: &str| {
+            format!(
+                "name: {name}\ndescription: test\nauthors: []\n\
+                 post_update:\n  global:\n    - program: sh\n      args: [-c, echo updated >> hook]\n\
+                 run:\n  default:\n    program: 'true'\n"
+            )
+        };
+        let remotes = Remotes::new();
+        let repo = remotes.repo("r", &hooked("v1"));
+        let manager = remotes.manager();
+        let path = manager.install(&remotes.source("u/r"), false).unwrap().path;
+
+        let update = manager.update("r", false).unwrap();
+        assert!(!update.changed && update.post_update.is_empty());
+        assert!(!path.join("hook").exists());
+
+        commit(&repo, "run.yml", &hooked("v2"));
+        let update = manager.update("r", false).unwrap();
+        assert!(update.changed);
+        assert_eq!(update.post_update.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(path.join("hook")).unwrap(),
+            "updated\n"
+        );
+        let (_, logs) = manager.logs("r", Some(Stage::PostUpdate)).unwrap();
+        assert_eq!(logs.len(), 1);
+    }
+
+    #[test]
+    fn local_update_only_on_change() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("tool");
+        std::fs::create_dir(&source).unwrap();
+        let manifest = |name: &str| {
+            format!(
+                "name: {name}\ndescription: test\nauthors: []\n\
+                 init:\n  global:\n    - program: sh\n      args: [-c, echo built >> built]\n\
+                 post_update:\n  global:\n    - program: sh\n      args: [-c, echo updated >> hook]\n\
+                 run:\n  default:\n    program: 'true'\n"
+            )
+        };
+        std::fs::write(source.join("run.yml"), manifest("v1")).unwrap();
+
+        let manager = Manager::new(tmp.path().join("home"))
+            .unwrap()
+            .with_executor(Silent);
+        let path = manager
+            .install(&PackageSource::new_local(&source), false)
+            .unwrap()
+            .path;
+
+        // Init output in the checkout doesn't make the copy look changed
+        let update = manager.update("tool", false).unwrap();
+        assert!(!update.changed);
+        assert!(update.init.is_empty() && update.post_update.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(path.join("built")).unwrap(),
+            "built\n"
+        );
+        assert!(!path.join("hook").exists());
+
+        std::fs::write(source.join("run.yml"), manifest("v2")).unwrap();
+        let update = manager.update("tool", false).unwrap();
+        assert!(update.changed);
+        assert_eq!(update.post_update.len(), 1);
+        assert_eq!(manager.info("tool").unwrap().package.name, "v2");
+        assert!(!manager.update("tool", false).unwrap().changed);
+    }
+
+    #[test]
+    fn failed_reinstall_keeps_old_version() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("tool");
+        std::fs::create_dir(&source).unwrap();
+        let manifest = |init: &str| {
+            let yaml = format!(
+                "name: Tool\ndescription: test\nauthors: []\n\
+                 init:\n  global:\n    - program: sh\n      args: [-c, \"{init}\"]\n\
+                 run:\n  default:\n    program: tool\n"
+            );
+            std::fs::write(source.join("run.yml"), yaml).unwrap();
+        };
+        let manager = Manager::new(tmp.path().join("home"))
+            .unwrap()
+            .with_options(Options {
+                quiet: true,
+                offline: true,
+            });
+
+        manifest("echo ${PACKAGE_DIR} > built");
+        let installed = manager
+            .install(&PackageSource::new_local(&source), false)
+            .unwrap()
+            .path;
+        // Init runs where the package stays, not in the staging directory
+        assert_eq!(
+            std::fs::read_to_string(installed.join("built")).unwrap(),
+            format!("{}\n", installed.display())
+        );
+
+        manifest("exit 1");
+        assert!(matches!(
+            manager.install(&PackageSource::new_local(&source), true),
+            Err(ManagerError::InitFailed(_))
+        ));
+        assert!(installed.join("built").exists());
+        assert_eq!(manager.installed_ids().unwrap(), ["_local/tool"]);
+        assert!(matches!(
+            manager.update("tool", false),
+            Err(ManagerError::InitFailed(_))
+        ));
+        assert!(installed.join("built").exists());
+
+        // Nothing is left behind in the repos directory
+        let leftovers = std::fs::read_dir(&manager.repos)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().file_name() != "_local")
+            .count();
+        assert_eq!(leftovers, 0);
+
+        // A git checkout keeps its commit and build, and the next update tries again
+        use crate::test_support::{commit, manifest as git_manifest, Remotes};
+        let remotes = Remotes::new();
+        let repo = remotes.repo("r", &git_manifest("v1", Some("touch built")));
+        let manager = remotes.manager();
+        let outcome = manager.install(&remotes.source("u/r"), false).unwrap();
+        commit(
+            &repo,
+            "run.yml",
+            &git_manifest("v2", Some("rm built; exit 1")),
+        );
+        for _ in 0..2 {
+            assert!(matches!(
+                manager.update("r", false),
+                Err(ManagerError::InitFailed(_))
+            ));
+            assert_eq!(head_commit(&outcome.path), outcome.commit);
+            assert!(outcome.path.join("built").exists());
+        }
+        let record = InstallRecord::load(&manager.state, "u/r").unwrap().unwrap();
+        assert_eq!(record.commit, outcome.commit);
+    }
+
+    #[test]
+    fn main_path_from_home() {
+        let path = |home: Option<&str>, run_that_home: Option<&str>| {
+            main_path(run_that_home.map(Into::into), home.map(Into::into))
+        };
+        assert_eq!(
+            path(Some("/home/u"), None),
+            PathBuf::from("/home/u/.run-that")
+        );
+        assert_eq!(
+            path(Some("/home/u"), Some("/data/rt")),
+            PathBuf::from("/data/rt")
+        );
+        assert!(path(None, None).is_absolute());
+    }
+
+    #[test]
+    fn git_install_update_run_remove() {
+        use crate::test_support::{commit, manifest, Remotes};
+
+        let remotes = Remotes::new();
+        let runs = "name: Tool\ndescription: test\nauthors: []\n\
+                    run:\n  default:\n    program: sh\n    args: [-c, 'echo \"$@\" | tee ran', sh]\n";
+        let repo = remotes.repo("r", runs);
+        let manager = remotes.manager().with_executor(Silent);
+
+        let outcome = manager.install(&remotes.source("u/r"), false).unwrap();
+        assert!(outcome.installed);
+        assert_eq!(outcome.id, "u/r");
+        assert_eq!(head_commit(&outcome.path), outcome.commit);
+        let record = InstallRecord::load(&manager.state, "u/r").unwrap().unwrap();
+        assert_eq!(record.commit, outcome.commit);
+        assert!(matches!(record.source, SourceRecord::Git(_)));
+        assert!(
+            !manager
+                .install(&remotes.source("u/r"), false)
+                .unwrap()
+                .installed
+        );
+
+        let args = ["a".to_string(), "--b".to_string()];
+        let run = manager.run("r", &args, BTreeMap::new()).unwrap();
+        assert!(run.success);
+        assert_eq!(
+            std::fs::read_to_string(outcome.path.join("ran")).unwrap(),
+            "a --b\n"
+        );
+        // Output of the program is kept in the run log
+        let (_, logs) = manager.logs("r", Some(Stage::Run)).unwrap();
+        let log = std::fs::read_to_string(&logs.last().unwrap().path).unwrap();
+        assert!(log.contains("a --b\n"), "{log}");
+
+        assert!(!manager.update("r", false).unwrap().changed);
+        let new = commit(&repo, "run.yml", &manifest("v2", None)).to_string();
+        let update = manager.update("r", false).unwrap();
+        assert!(update.changed);
+        assert_eq!(update.commit.as_deref(), Some(new.as_str()));
+        let record = InstallRecord::load(&manager.state, "u/r").unwrap().unwrap();
+        assert_eq!(record.commit, Some(new));
+        assert_eq!(manager.info("r").unwrap().package.name, "v2");
+
+        manager.remove("r", false).unwrap();
+        assert!(!manager.repos.join("u").exists());
+        assert!(InstallRecord::load(&manager.state, "u/r")
+            .unwrap()
+            .is_none());
+        assert!(manager.installed_ids().unwrap().is_empty());
+    }
+
+    #[test]
+    fn version_range() {
+        use crate::test_support::{commit, manifest, tag, Remotes};
+
+        let remotes = Remotes::new();
+        let repo = remotes.repo("r", &manifest("v1.2.0", None));
+        let release = |version: &str| {
+            commit(&repo, "run.yml", &manifest(version, None));
+            tag(&repo, version)
+        };
+        tag(&repo, "v1.2.0");
+        let newest = release("v1.3.1");
+        release("v2.0.0");
+
+        let manager = remotes.manager();
+        let outcome = manager.install(&remotes.source("u/r@^1.2"), false).unwrap();
+        assert_eq!(outcome.commit.as_deref(), Some(newest.as_str()));
+        let record = InstallRecord::load(&manager.state, "u/r@^1.2")
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.tag.as_deref(), Some("v1.3.1"));
+        assert_eq!(
+            record.source,
+            SourceRecord::Git("github.com:u/r@^1.2".into())
+        );
+
+        let newest = release("v1.4.0");
+        release("v2.1.0");
+        let outcome = manager.update("r", false).unwrap();
+        assert!(outcome.changed);
+        assert_eq!(outcome.commit.as_deref(), Some(newest.as_str()));
+        let record = InstallRecord::load(&manager.state, "u/r@^1.2")
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.tag.as_deref(), Some("v1.4.0"));
+        assert!(!manager.update("r", false).unwrap().changed);
+
+        assert!(matches!(
+            manager.install(&remotes.source("u/r@^3"), true),
+            Err(ManagerError::Fetch(_, GitCloneError::NoMatchingTag(_)))
+        ));
+    }
+
+    #[test]
+    fn side_by_side_versions() {
+        use crate::test_support::{commit, manifest, tag, Remotes};
+
+        let remotes = Remotes::new();
+        let repo = remotes.repo("r", &manifest("v1", None));
+        tag(&repo, "v1");
+        commit(&repo, "run.yml", &manifest("v2", None));
+        tag(&repo, "v2");
+
+        let manager = remotes.manager();
+        for address in ["u/r@v1", "u/r@v2"] {
+            manager.install(&remotes.source(address), false).unwrap();
+        }
+        assert_eq!(manager.installed_ids().unwrap(), ["u/r@v1", "u/r@v2"]);
+        let name = |id: &str| get_package_info(&manager.repos.join(id)).unwrap().name;
+        assert_eq!((name("u/r@v1"), name("u/r@v2")), ("v1".into(), "v2".into()));
+
+        assert!(matches!(
+            manager.resolve_id("r"),
+            Err(ManagerError::Ambiguous(..))
+        ));
+        assert_eq!(manager.resolve_id("r@v1").unwrap(), "u/r@v1");
+        assert_eq!(manager.activate("u/r@v2").unwrap(), "u/r@v2");
+        assert_eq!(manager.resolve_id("r").unwrap(), "u/r@v2");
+        let active: Vec<_> = manager.list().unwrap().iter().map(|p| p.active).collect();
+        assert_eq!(active, [false, true]);
+
+        manager.remove("r@v2", false).unwrap();
+        assert_eq!(active_version(&manager.state, "u/r").unwrap(), None);
+        assert_eq!(manager.resolve_id("r").unwrap(), "u/r@v1");
+    }
+
+    #[test]
+    fn rollback() {
+        use crate::test_support::{commit, Remotes};
+
+        // The second step only depends on `deps`, which no commit changes
+        let manifest = |name: &str| {
+            format!(
+                "name: {name}\ndescription: test\nauthors: []\n\
+                 init:\n  global:\n    - program: sh\n      args: [-c, echo {name} >> built]\n\
+                 \x20   - program: sh\n      args: [-c, echo deps >> fetched]\n      inputs: [deps]\n\
+                 run:\n  default:\n    program: tool\n"
+            )
+        };
+        let remotes = Remotes::new();
+        let repo = remotes.repo("r", &manifest("v1"));
+        commit(&repo, "deps", "serde");
+        let first = repo.head().unwrap().target().unwrap().to_string();
+
+        let manager = remotes.manager();
+        manager.install(&remotes.source("u/r"), false).unwrap();
+        assert!(matches!(
+            manager.rollback("r", None),
+            Err(ManagerError::NoRevision(..))
+        ));
+
+        let second = commit(&repo, "run.yml", &manifest("v2")).to_string();
+        manager.update("r", false).unwrap();
+        let outcome = manager.rollback("r", None).unwrap();
+        assert_eq!(outcome.from.as_deref(), Some(second.as_str()));
+        assert_eq!(outcome.to.commit, first);
+        // Steps depending on the commit rerun, the ones with unchanged inputs are reused
+        let cached: Vec<_> = outcome.init.iter().map(|s| s.cached).collect();
+        assert_eq!(cached, [false, true]);
+        let path = manager.repos.join("u/r");
+        assert_eq!(
+            std::fs::read_to_string(path.join("fetched")).unwrap(),
+            "deps\n"
+        );
+        assert_eq!(get_package_info(&path).unwrap().name, "v1");
+        assert_eq!(
+            std::fs::read_to_string(path.join("built")).unwrap(),
+            "v1\nv2\nv1\n"
+        );
+
+        let record = InstallRecord::load(&manager.state, "u/r").unwrap().unwrap();
+        let history: Vec<_> = record.history.iter().map(|r| r.commit.as_str()).collect();
+        assert_eq!(history, [&first, &second, &first]);
+
+        // Rolling back again returns to the newer revision, also by its commit
+        let outcome = manager.rollback("r", Some(&second[..7])).unwrap();
+        assert_eq!(outcome.to.commit, second);
+        assert_eq!(head_commit(&path), Some(second));
+        assert!(matches!(
+            manager.rollback("r", Some("5")),
+            Err(ManagerError::NoRevision(..))
+        ));
+    }
+}
@@ -0,0 +1,250 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::package::Package;
+
+/// Key under `[package.metadata]` in `Cargo.toml` that holds the manifest
+pub const CARGO_METADATA_KEY: &str = "run-that";
+
+/// Files searched for a manifest, in order of precedence
+pub const MANIFEST_LOOKUP: &[(&str, ManifestFormat)] = &[
+    ("run.yml", ManifestFormat::Yaml),
+    ("run.yaml", ManifestFormat::Yaml),
+    (".run-that.yml", ManifestFormat::Yaml),
+    ("run.toml", ManifestFormat::Toml),
+    ("run.json", ManifestFormat::Json),
+    ("Cargo.toml", ManifestFormat::Cargo),
+];
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("invalid yaml: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("invalid toml: {0}")]
+    TomlDe(#[from] toml::de::Error),
+    #[error("could not write toml: {0}")]
+    TomlSer(#[from] toml::ser::Error),
+    #[error("invalid json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Cargo.toml has no [package.metadata.{CARGO_METADATA_KEY}] table")]
+    MissingCargoMetadata,
+    #[error("Cargo.toml already has a [package.metadata.{CARGO_METADATA_KEY}] table")]
+    CargoMetadataExists,
+    #[error("cannot tell manifest format of {0:?}")]
+    UnknownFormat(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestFormat {
+    Yaml,
+    Toml,
+    Json,
+    Cargo, // `[package.metadata.run-that]` table inside Cargo.toml
+}
+
+impl ManifestFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        if path.file_name()? == "Cargo.toml" {
+            return Some(Self::Cargo);
+        }
+
+        match path.extension()?.to_str()? {
+            "yml" | "yaml" => Some(Self::Yaml),
+            "toml" => Some(Self::Toml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub fn parse(self, text: &str) -> Result<Package, ManifestError> {
+        Ok(match self {
+            Self::Yaml => serde_yaml::from_str(text)?,
+            Self::Toml => toml::from_str(text)?,
+            Self::Json => serde_json::from_str(text)?,
+            Self::Cargo => {
+                let value = cargo_metadata(text)?.ok_or(ManifestError::MissingCargoMetadata)?;
+                Package::deserialize(value)?
+            }
+        })
+    }
+
+    /// Renders the package in this format.
+    ///
+    /// For [`ManifestFormat::Cargo`] only the metadata table is rendered, so it can be
+    /// appended to an existing `Cargo.toml`.
+    pub fn render(self, package: &Package) -> Result<String, ManifestError> {
+        Ok(match self {
+            Self::Yaml => serde_yaml::to_string(package)?,
+            Self::Toml => toml::to_string(package)?,
+            Self::Json => serde_json::to_string_pretty(package)? + "\n",
+            Self::Cargo => {
+                let mut metadata = toml::Table::new();
+                metadata.insert(
+                    CARGO_METADATA_KEY.to_string(),
+                    toml::Value::try_from(package)?,
+                );
+                let mut cargo_package = toml::Table::new();
+                cargo_package.insert("metadata".to_string(), metadata.into());
+                let mut root = toml::Table::new();
+                root.insert("package".to_string(), cargo_package.into());
+                toml::to_string(&root)?
+            }
+        })
+    }
+}
+
+fn cargo_metadata(text: &str) -> Result<Option<toml::Value>, ManifestError> {
+    let mut root: toml::Table = toml::from_str(text)?;
+    Ok(root
+        .remove("package")
+        .and_then(|mut p| p.as_table_mut()?.remove("metadata"))
+        .and_then(|mut m| m.as_table_mut()?.remove(CARGO_METADATA_KEY)))
+}
+
+/// Finds the manifest of the package in `dir` using [`MANIFEST_LOOKUP`].
+///
+/// `Cargo.toml` is only considered when it contains the metadata table.
+pub fn find_manifest(dir: &Path) -> Option<(PathBuf, ManifestFormat)> {
+    MANIFEST_LOOKUP.iter().find_map(|(file, format)| {
+        let path = dir.join(file);
+        if !path.is_file() {
+            return None;
+        }
+
+        if *format == ManifestFormat::Cargo {
+            let text = std::fs::read_to_string(&path).ok()?;
+            cargo_metadata(&text).ok()??;
+        }
+
+        Some((path, *format))
+    })
+}
+
+/// Like [`find_manifest`], for packages that aren't on disk, `read` returns content of a file
+/// of the package or `None` if it doesn't exist
+pub fn find_manifest_with(
+    mut read: impl FnMut(&str) -> Option<String>,
+) -> Option<(&'static str, ManifestFormat, String)> {
+    MANIFEST_LOOKUP.iter().find_map(|(file, format)| {
+        let text = read(file)?;
+        if *format == ManifestFormat::Cargo {
+            cargo_metadata(&text).ok()??;
+        }
+
+        Some((*file, *format, text))
+    })
+}
+
+/// Writes `package` to `path`, appending the metadata table if `path` is an existing `Cargo.toml`
+pub fn write_manifest(
+    path: &Path,
+    format: ManifestFormat,
+    package: &Package,
+) -> Result<(), crate::manager::PackageInfoError> {
+    use crate::manager::PackageInfoError;
+
+    let invalid = |e| PackageInfoError::ParsingFileFailed(path.to_path_buf(), Box::new(e));
+    let write_failed = |e| PackageInfoError::WritingFileFailed(path.to_path_buf(), e);
+    let rendered = format.render(package).map_err(invalid)?;
+
+    if format == ManifestFormat::Cargo && path.exists() {
+        let mut text = std::fs::read_to_string(path)
+            .map_err(|e| PackageInfoError::ReadingFileFailed(path.to_path_buf(), e))?;
+        if cargo_metadata(&text).map_err(invalid)?.is_some() {
+            return Err(invalid(ManifestError::CargoMetadataExists));
+        }
+
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push('\n');
+        text.push_str(&rendered);
+        std::fs::write(path, text).map_err(write_failed)?;
+    } else {
+        std::fs::write(path, rendered).map_err(write_failed)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"
+        name: Test Package
+        description: This is a test package
+        authors:
+        - ur-fault
+        init:
+          global:
+            - program: cargo
+              args:
+              - build
+        run:
+          default:
+            program: cargo
+            args:
+            - run
+          win: !custom
+            program: cargo.exe
+          mac: null
+        checks:
+          global:
+          - program: cargo
+            args:
+            - --version"#;
+
+    #[test]
+    fn formats_roundtrip() {
+        let package = ManifestFormat::Yaml.parse(YAML).unwrap();
+
+        for format in [
+            ManifestFormat::Yaml,
+            ManifestFormat::Toml,
+            ManifestFormat::Json,
+            ManifestFormat::Cargo,
+        ] {
+            let rendered = format.render(&package).unwrap();
+            assert_eq!(format.parse(&rendered).unwrap(), package, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn cargo_metadata_table() {
+        let cargo = r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+
+            [package.metadata.run-that]
+            name = "Foo"
+            description = "Foo launcher"
+            authors = []
+            run.default = { program = "cargo", args = ["run"] }
+            "#;
+
+        let package = ManifestFormat::Cargo.parse(cargo).unwrap();
+        assert_eq!(package.name, "Foo");
+        assert_eq!(package.run.default.unwrap().args, ["run"]);
+
+        assert!(matches!(
+            ManifestFormat::Cargo.parse("[package]\nname = \"foo\""),
+            Err(ManifestError::MissingCargoMetadata)
+        ));
+    }
+
+    #[test]
+    fn format_from_path() {
+        let from = |p: &str| ManifestFormat::from_path(Path::new(p));
+        assert_eq!(from("run.yml"), Some(ManifestFormat::Yaml));
+        assert_eq!(from(".run-that.yml"), Some(ManifestFormat::Yaml));
+        assert_eq!(from("a/run.toml"), Some(ManifestFormat::Toml));
+        assert_eq!(from("run.json"), Some(ManifestFormat::Json));
+        assert_eq!(from("a/Cargo.toml"), Some(ManifestFormat::Cargo));
+        assert_eq!(from("run.txt"), None);
+    }
+}
@@ -0,0 +1,329 @@
+use git2::{Oid, Repository, Sort};
+use serde::Serialize;
+
+use crate::{
+    manager::{Manager, ManagerError},
+    package_index::{head_commit, GitSource, GitSpecifier},
+    semver::Version,
+    state::{InstallRecord, SourceRecord},
+};
+
+/// What an installed git package follows
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "name")]
+pub enum Pin {
+    Branch(String),
+    Tag(String),
+    Commit(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutdatedEntry {
+    pub id: String,
+    pub pin: Pin,
+    pub installed: Option<String>, // Checked out commit
+    pub latest: Option<String>,    // Tip of the branch, or commit of the newest tag
+    pub behind: usize,             // Commits the checkout is behind the branch tip
+    pub newer_tags: Vec<String>,   // Version tags newer than the pinned one, newest first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // Why the remote couldn't be compared, the other packages are still checked
+}
+
+impl OutdatedEntry {
+    pub fn is_outdated(&self) -> bool {
+        self.behind > 0 || !self.newer_tags.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Changelog {
+    pub id: String,
+    pub from: Option<String>,         // Installed commit
+    pub to: Option<String>,           // Commit `outdated` reports as the latest
+    pub commits: Vec<ChangelogEntry>, // Newest first
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangelogEntry {
+    pub commit: String,
+    pub summary: String,
+    pub author: String,
+    pub time: i64, // Unix timestamp of the commit
+}
+
+/// Installed package compared with the fetched mirror of its remote
+struct Upstream {
+    entry: OutdatedEntry,
+    mirror: Repository,
+    url: String,
+}
+
+/// Installed git package before its remote is fetched
+struct Pinned {
+    entry: OutdatedEntry,
+    url: String,
+}
+
+impl Manager {
+    /// Fetches the remotes of installed git packages and reports how far behind they are.
+    ///
+    /// Branch pins are compared with the branch tip, tag pins with newer version tags.
+    /// Packages pinned to a commit are never outdated, other packages are skipped. A remote
+    /// that can't be fetched is recorded in the `error` of its entry.
+    pub fn outdated(&self) -> Result<Vec<OutdatedEntry>, ManagerError> {
+        let mut entries = vec![];
+        for id in self.installed_ids()? {
+            let Some(pinned) = self.pinned(&id)? else {
+                continue;
+            };
+            let mut entry = pinned.entry.clone();
+            match self.compare(pinned) {
+                Ok(upstream) => entry = upstream.entry,
+                Err(e) => entry.error = Some(describe(e)),
+            }
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    /// Commits between the installed revision of a git package and the latest one
+    pub fn changelog(&self, name: &str) -> Result<Changelog, ManagerError> {
+        let id = self.resolve_id(name)?;
+        let Some(Upstream { entry, mirror, url }) = self.upstream(&id)? else {
+            return Ok(Changelog {
+                id,
+                from: None,
+                to: None,
+                commits: vec![],
+            });
+        };
+
+        let commits = match &entry.latest {
+            Some(latest) => commits_between(&mirror, latest, entry.installed.as_deref())
+                .map_err(ManagerError::fetch(url))?,
+            None => vec![],
+        };
+
+        Ok(Changelog {
+            id,
+            from: entry.installed,
+            to: entry.latest,
+            commits,
+        })
+    }
+
+    /// `None` for packages not installed from git
+    fn upstream(&self, id: &str) -> Result<Option<Upstream>, ManagerError> {
+        match self.pinned(id)? {
+            Some(pinned) => self.compare(pinned).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// What an installed package follows, read without touching its remote
+    fn pinned(&self, id: &str) -> Result<Option<Pinned>, ManagerError> {
+        let Some(InstallRecord {
+            source: SourceRecord::Git(address),
+            tag: resolved,
+            ..
+        }) = InstallRecord::load(&self.state, id)?
+        else {
+            return Ok(None);
+        };
+
+        let source = GitSource::new(address, self.config())?;
+        let path = self.repos.join(id);
+        let installed = head_commit(&path);
+        let pin = match source.spec() {
+            Some(GitSpecifier::Branch(b)) => Pin::Branch(b.clone()),
+            Some(GitSpecifier::Tag(t)) => Pin::Tag(t.clone()),
+            // Newer tags are reported even outside of the range, `update` only moves within it
+            Some(GitSpecifier::Range(_)) => Pin::Tag(resolved.unwrap_or_default()),
+            Some(GitSpecifier::Commit(c)) => Pin::Commit(c.clone()),
+            None => match checked_out_branch(&path) {
+                Some(branch) => Pin::Branch(branch),
+                None => Pin::Commit(installed.clone().unwrap_or_default()),
+            },
+        };
+
+        let entry = OutdatedEntry {
+            id: id.to_string(),
+            pin,
+            latest: installed.clone(),
+            installed,
+            behind: 0,
+            newer_tags: vec![],
+            error: None,
+        };
+        Ok(Some(Pinned {
+            entry,
+            url: source.url(),
+        }))
+    }
+
+    /// Fetches the remote of the package and compares it with the checkout
+    fn compare(&self, pinned: Pinned) -> Result<Upstream, ManagerError> {
+        let Pinned { mut entry, url } = pinned;
+        let fetch = || ManagerError::fetch(&url);
+        let mirror = self
+            .cache()
+            .mirror(&url)
+            .map_err(ManagerError::fetch(&url))?;
+        let mirror = Repository::open_bare(mirror).map_err(fetch())?;
+
+        match &entry.pin {
+            Pin::Branch(branch) => {
+                let tip = mirror
+                    .revparse_single(&format!("refs/heads/{branch}"))
+                    .and_then(|o| o.peel_to_commit())
+                    .map_err(fetch())?
+                    .id();
+                entry.behind = behind(&mirror, tip, entry.installed.as_deref()).map_err(fetch())?;
+                entry.latest = Some(tip.to_string());
+            }
+            Pin::Tag(tag) => {
+                entry.newer_tags = newer_tags(&mirror, tag).map_err(fetch())?;
+                if let Some(newest) = entry.newer_tags.first() {
+                    let commit = mirror
+                        .revparse_single(&format!("refs/tags/{newest}"))
+                        .and_then(|o| o.peel_to_commit())
+                        .map_err(fetch())?;
+                    entry.latest = Some(commit.id().to_string());
+                }
+            }
+            Pin::Commit(_) => {}
+        }
+
+        Ok(Upstream { entry, mirror, url })
+    }
+}
+
+/// Message of the error followed by its causes
+fn describe(error: ManagerError) -> String {
+    let error = crate::error::Error::from(error);
+    std::iter::once(error.to_string())
+        .chain(error.causes())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// Commits reachable from `latest` but not from `installed`, newest first
+fn commits_between(
+    repo: &Repository,
+    latest: &str,
+    installed: Option<&str>,
+) -> Result<Vec<ChangelogEntry>, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    walk.push(Oid::from_str(latest)?)?;
+    if let Some(installed) = installed {
+        // The installed commit may be gone after a force push, then all commits are new
+        let _ = walk.hide(Oid::from_str(installed)?);
+    }
+
+    walk.map(|commit| {
+        let commit = repo.find_commit(commit?)?;
+        let author = commit.author().name().unwrap_or_default().to_string();
+        Ok(ChangelogEntry {
+            commit: commit.id().to_string(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+            author,
+            time: commit.time().seconds(),
+        })
+    })
+    .collect()
+}
+
+/// Branch checked out in the repository at `path`, `None` for a detached head
+fn checked_out_branch(path: &std::path::Path) -> Option<String> {
+    let repo = Repository::open(path).ok()?;
+    let head = repo.head().ok()?;
+    head.is_branch()
+        .then(|| head.shorthand().map(str::to_string))
+        .flatten()
+}
+
+/// Number of commits reachable from `tip` but not from `installed`
+fn behind(repo: &Repository, tip: Oid, installed: Option<&str>) -> Result<usize, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    walk.push(tip)?;
+    if let Some(installed) = installed {
+        let _ = walk.hide(Oid::from_str(installed)?);
+    }
+
+    Ok(walk.count())
+}
+
+/// Version tags of the repository newer than `tag`, newest first.
+///
+/// Pre-releases are only included when `tag` is one, nothing is newer than a tag that
+/// isn't a version.
+fn newer_tags(repo: &Repository, tag: &str) -> Result<Vec<String>, git2::Error> {
+    let Some(current) = Version::parse(tag) else {
+        return Ok(vec![]);
+    };
+
+    let names = repo.tag_names(None)?;
+    let mut newer: Vec<_> = names
+        .iter()
+        .flatten()
+        .filter_map(|name| Some((Version::parse(name)?, name.to_string())))
+        .filter(|(version, _)| *version > current)
+        .filter(|(version, _)| !version.is_prerelease() || current.is_prerelease())
+        .collect();
+    newer.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(newer.into_iter().map(|(_, name)| name).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{commit, manifest, tag, Remotes};
+
+    use super::*;
+
+    #[test]
+    fn outdated_and_changelog() {
+        let remotes = Remotes::new();
+        let repos: Vec<_> = ["branch", "tagged", "unreachable"]
+            .into_iter()
+            .map(|name| remotes.repo(name, &manifest("Tool", None)))
+            .collect();
+        tag(&repos[1], "v1");
+
+        let manager = remotes.manager();
+        for address in ["u/branch", "u/tagged@v1", "u/unreachable"] {
+            manager.install(&remotes.source(address), false).unwrap();
+        }
+        std::fs::remove_dir_all(repos[2].workdir().unwrap()).unwrap();
+
+        for repo in &repos[..2] {
+            commit(repo, "notes", "first change");
+            commit(repo, "notes", "second change");
+        }
+        tag(&repos[1], "v2");
+        tag(&repos[1], "v3-rc");
+
+        let outdated = manager.outdated().unwrap();
+        assert_eq!(outdated.len(), 3);
+        assert_eq!(outdated[0].pin, Pin::Branch("main".to_string()));
+        assert_eq!(outdated[0].behind, 2);
+        assert_eq!(outdated[1].pin, Pin::Tag("v1".to_string()));
+        assert_eq!(outdated[1].newer_tags, ["v2"]);
+        assert!(outdated[..2].iter().all(|e| e.is_outdated()));
+        // An unreachable remote doesn't stop the others from being checked
+        assert!(!outdated[2].is_outdated());
+        assert!(outdated[2]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("could not get"));
+        assert!(outdated[..2].iter().all(|e| e.error.is_none()));
+
+        let changelog = manager.changelog("branch").unwrap();
+        let summaries: Vec<_> = changelog.commits.iter().map(|c| &c.summary).collect();
+        assert_eq!(summaries, ["second change", "first change"]);
+        assert_eq!(changelog.to, outdated[0].latest);
+        assert_eq!(manager.changelog("tagged").unwrap().commits.len(), 2);
+    }
+}
@@ -0,0 +1,124 @@
+//! Machine readable output.
+//!
+//! With `--format json` or `--format yaml` every command prints exactly one document
+//! to stdout, an envelope around the command's result:
+//!
+//! ```yaml
+//! schema_version: 1   # bumped on every incompatible change
+//! kind: check         # tells the shape of `data`
+//! data: ...
+//! ```
+//!
+//! Kinds of schema version 1 and their `data`:
+//!
+//! | kind          | data                                                                |
+//! |---------------|---------------------------------------------------------------------|
+//! | `info`        | `{ path, package }`, `package` is the manifest as in `run.yml`, installed packages add `id`, `record` and `init: [{ command, cached }]` |
+//! | `preview`     | `{ address, commit, manifest, package, checks, supported }`, for `info <address>` |
+//! | `installed`   | list of `{ id, path, record, package, active }`, `active` tells the version used when the package is named without one |
+//! | `install_path`| `{ path }`                                                          |
+//! | `check`       | `{ package, passed, steps }`                                        |
+//! | `install`     | `{ id, path, installed, commit, checks, init }`, also for `import`  |
+//! | `export`      | `{ path, bundle: { version, record, sha256 } }`                     |
+//! | `convert`     | `{ path, format, manifest }`, `path` of the written file, or `manifest` text when printing |
+//! | `update`      | `{ id, changed, commit, init, post_update }`                        |
+//! | `outdated`    | list of `{ id, pin: { kind, name }, installed, latest, behind, newer_tags, error }`, `kind` is `branch`, `tag` or `commit`, `error` only when the remote couldn't be fetched |
+//! | `changelog`   | `{ id, from, to, commits: [{ commit, summary, author, time }] }`, newest commit first |
+//! | `active`      | `{ package, id }`, the version `use` made the default               |
+//! | `rollback`    | `{ id, from, to: { commit, tag, at }, init }`                       |
+//! | `remove`      | `{ id, shims, uninstall }`, `shims` are the removed launchers as in `link` |
+//! | `link`        | `{ name, id, path }`                                                |
+//! | `doctor`      | `{ main_path, bin_path, bin_on_path, broken_shims }`                |
+//! | `run`         | `{ id, command, success, code, pre_run, post_run }`                 |
+//! | `service`     | `{ id, pid, started_at, command, args, params, log, running, uptime }`, for `start` and `restart` |
+//! | `stop`        | `{ id, pid, was_running, killed }`                                  |
+//! | `status`      | list of services as in `service`, `running` is `false` for stale pid files |
+//! | `cache`       | list of `{ url, path, size, fetched_at }`                           |
+//! | `cache_prune` | list of removed mirrors, as in `cache`                              |
+//! | `gc`          | `{ dry_run, reclaimed, entries: [{ kind, target, size, skipped, steps }] }` |
+//! | `logs`        | `{ id, logs: [{ path, stage, created_at, size }] }`                 |
+//! | `log`         | `{ id, log, content }`, the latest log for `--last`, `null` if none |
+//! | `config`      | list of `{ key, value }`, the effective settings                    |
+//! | `config_value`| `{ key, value }`, `value` is `null` after `config unset`            |
+//! | `error`       | `{ message, kind, exit_code, hint, causes }`, see [`crate::error`] for the kinds |
+//!
+//! A step is `{ command: { program, args, ... }, success, code, error, timed_out, attempts, cached }`,
+//! where `code` is the exit code (`null` if killed by a signal or a timeout) and `error`
+//! is set when the program could not be started or timed out. `cached` init steps were
+//! skipped because nothing they depend on changed.
+//!
+//! The human format is colored unless `NO_COLOR` is set or stdout isn't a terminal.
+
+use std::io::IsTerminal;
+
+use serde::{Deserialize, Serialize};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Human,
+    Json,
+    Yaml,
+}
+
+#[derive(Debug, Serialize)]
+struct Envelope<'a, T> {
+    schema_version: u32,
+    kind: &'a str,
+    data: &'a T,
+}
+
+impl OutputFormat {
+    pub fn is_human(self) -> bool {
+        self == Self::Human
+    }
+
+    /// Turns colors off when they are not wanted
+    pub fn setup_colors(self) {
+        if !self.is_human()
+            || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+            || !std::io::stdout().is_terminal()
+        {
+            colored::control::set_override(false);
+        }
+    }
+
+    /// Renders `data` wrapped in the versioned envelope, `None` for the human format
+    pub fn render<T: Serialize>(self, kind: &str, data: &T) -> Option<String> {
+        let envelope = Envelope {
+            schema_version: SCHEMA_VERSION,
+            kind,
+            data,
+        };
+
+        match self {
+            Self::Human => None,
+            Self::Json => Some(
+                serde_json::to_string_pretty(&envelope).expect("output is always serializable"),
+            ),
+            Self::Yaml => {
+                Some(serde_yaml::to_string(&envelope).expect("output is always serializable"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope() {
+        let json = OutputFormat::Json.render("remove", &[1, 2]).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "schema_version": SCHEMA_VERSION, "kind": "remove", "data": [1, 2] })
+        );
+
+        assert_eq!(OutputFormat::Human.render("remove", &()), None);
+    }
+}
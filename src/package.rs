@@ -1,9 +1,21 @@
 use colored::*;
-use std::fmt::{Display, Formatter};
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    path::Path,
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{Error as _, IgnoredAny, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
 
-#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+use crate::{
+    target::{Selector, Target, Targets},
+    vars::{self, VarError, Vars},
+};
+
+#[derive(Debug, Deserialize, Clone, Serialize, Default, PartialEq, Eq)]
 pub struct Package {
     pub name: String,         // Name of the package
     pub description: String,  // Description of the package
@@ -13,6 +25,35 @@ pub struct Package {
     pub run: RunCommands,     // Commands to run
     #[serde(default)]
     pub checks: CommandSet, // Checks if required programs are available
+    #[serde(default, skip_serializing_if = "CommandSet::is_empty")]
+    pub clean: CommandSet, // Commands removing build outputs, run by `gc`
+    #[serde(default, skip_serializing_if = "CommandSet::is_empty")]
+    pub pre_run: CommandSet, // Commands run before `run` and `start`, failing aborts them
+    #[serde(default, skip_serializing_if = "CommandSet::is_empty")]
+    pub post_run: CommandSet, // Commands run after `run` exits, failing is only reported
+    #[serde(default, skip_serializing_if = "CommandSet::is_empty")]
+    pub post_update: CommandSet, // Commands run after an update changed the package
+    #[serde(default, skip_serializing_if = "CommandSet::is_empty")]
+    pub uninstall: CommandSet, // Commands run before removal, failing aborts it
+}
+
+impl Package {
+    /// Checks that all commands use only known variables
+    pub fn lint(&self) -> Result<(), VarError> {
+        let sets = [
+            &self.init,
+            &self.checks,
+            &self.clean,
+            &self.pre_run,
+            &self.post_run,
+            &self.post_update,
+            &self.uninstall,
+        ];
+        sets.iter()
+            .flat_map(|s| s.all())
+            .chain(self.run.all())
+            .try_for_each(Command::lint)
+    }
 }
 
 impl Display for Package {
@@ -40,20 +81,77 @@ impl Display for Package {
             "Commands run to check before installing:\n{}",
             self.checks
         )?;
+        if !self.clean.is_empty() {
+            writeln!(f, "Commands run to clean build outputs:\n{}", self.clean)?;
+        }
+        if !self.pre_run.is_empty() {
+            writeln!(f, "Commands run before running:\n{}", self.pre_run)?;
+        }
+        if !self.post_run.is_empty() {
+            writeln!(f, "Commands run after running:\n{}", self.post_run)?;
+        }
+        if !self.post_update.is_empty() {
+            writeln!(f, "Commands run after updating:\n{}", self.post_update)?;
+        }
+        if !self.uninstall.is_empty() {
+            writeln!(f, "Commands run before removing:\n{}", self.uninstall)?;
+        }
 
         Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize, Default, PartialEq, Eq)]
+/// Commands for all platforms and for specific targets.
+///
+/// On a platform, `global` commands run first, then the ones of its `win`, `linux` or `mac`
+/// key and then of every matching `cfg(...)` selector, in the order they are written.
+#[derive(Debug, Clone, Serialize, Default, PartialEq, Eq)]
 pub struct CommandSet {
     pub global: Vec<Command>, // Default checks to run
-    #[serde(default)]
-    pub win: Vec<Command>, // Checks to run on windows
-    #[serde(default)]
-    pub linux: Vec<Command>, // Checks to run on linux
-    #[serde(default)]
-    pub mac: Vec<Command>, // Checks to run on mac
+    pub win: Vec<Command>,    // Checks to run on windows
+    pub linux: Vec<Command>,  // Checks to run on linux
+    pub mac: Vec<Command>,    // Checks to run on mac
+    #[serde(flatten)]
+    pub targets: Targets<Vec<Command>>, // Checks to run on targets matching a selector
+}
+
+impl<'de> Deserialize<'de> for CommandSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SetVisitor;
+
+        impl<'de> Visitor<'de> for SetVisitor {
+            type Value = CommandSet;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                write!(f, "a command set")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut set = CommandSet::default();
+                let mut global = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "global" => global = Some(map.next_value()?),
+                        "win" => set.win = map.next_value()?,
+                        "linux" => set.linux = map.next_value()?,
+                        "mac" => set.mac = map.next_value()?,
+                        key if Selector::is_selector(key) => {
+                            let selector = Selector::parse(key).map_err(A::Error::custom)?;
+                            set.targets.push(selector, map.next_value()?);
+                        }
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                set.global = global.ok_or_else(|| A::Error::missing_field("global"))?;
+                Ok(set)
+            }
+        }
+
+        deserializer.deserialize_map(SetVisitor)
+    }
 }
 
 impl Display for CommandSet {
@@ -61,6 +159,12 @@ impl Display for CommandSet {
         fn print_checks(f: &mut Formatter<'_>, cmds: &[Command]) -> Result<(), std::fmt::Error> {
             for command in cmds {
                 writeln!(f, "  \t{command}")?;
+                if let Some(description) = &command.description {
+                    writeln!(f, "  \t  {}", description.italic())?;
+                }
+                if let Some(options) = command.options() {
+                    writeln!(f, "  \t  ({options})")?;
+                }
             }
 
             writeln!(f)?;
@@ -96,19 +200,103 @@ impl Display for CommandSet {
             print_checks(f, &self.mac)?;
         }
 
+        for (selector, commands) in self.targets.iter() {
+            writeln!(f, "  Commands for {selector}:")?;
+            print_checks(f, commands)?;
+        }
+
         Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Clone, Default, Serialize, PartialEq, Eq)]
+impl CommandSet {
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty()
+            && self.win.is_empty()
+            && self.linux.is_empty()
+            && self.mac.is_empty()
+            && self.targets.is_empty()
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Command> {
+        self.global
+            .iter()
+            .chain(&self.win)
+            .chain(&self.linux)
+            .chain(&self.mac)
+            .chain(self.targets.iter().flat_map(|(_, c)| c))
+    }
+
+    /// Commands to run on `target`, global ones first
+    pub fn for_target<'a>(&'a self, target: &'a Target) -> impl Iterator<Item = &'a Command> {
+        let os: &[Command] = match target.os.as_str() {
+            "windows" => &self.win,
+            "linux" => &self.linux,
+            "macos" => &self.mac,
+            _ => &[],
+        };
+
+        self.global
+            .iter()
+            .chain(os)
+            .chain(self.targets.matching(target).flatten())
+    }
+
+    /// Commands to run on the current platform
+    pub fn for_current_target(&self) -> impl Iterator<Item = &Command> {
+        self.for_target(Target::current())
+    }
+}
+
+/// Command to run the package, by platform.
+///
+/// The first `cfg(...)` selector matching the platform wins, then its `win`, `linux`
+/// or `mac` key and then `default`.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
 pub struct RunCommands {
     pub default: Option<Command>, // Default command to run
-    #[serde(default)]
-    pub win: RunCommand, // Command to run on windows
-    #[serde(default)]
-    pub linux: RunCommand, // Command to run on linux
-    #[serde(default)]
-    pub mac: RunCommand, // Command to run on mac
+    pub win: RunCommand,          // Command to run on windows
+    pub linux: RunCommand,        // Command to run on linux
+    pub mac: RunCommand,          // Command to run on mac
+    #[serde(flatten)]
+    pub targets: Targets<RunCommand>, // Commands to run on targets matching a selector
+}
+
+impl<'de> Deserialize<'de> for RunCommands {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RunVisitor;
+
+        impl<'de> Visitor<'de> for RunVisitor {
+            type Value = RunCommands;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                write!(f, "run commands")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut run = RunCommands::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "default" => run.default = map.next_value()?,
+                        "win" => run.win = map.next_value()?,
+                        "linux" => run.linux = map.next_value()?,
+                        "mac" => run.mac = map.next_value()?,
+                        key if Selector::is_selector(key) => {
+                            let selector = Selector::parse(key).map_err(A::Error::custom)?;
+                            run.targets.push(selector, map.next_value()?);
+                        }
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                Ok(run)
+            }
+        }
+
+        deserializer.deserialize_map(RunVisitor)
+    }
 }
 
 impl Display for RunCommands {
@@ -123,7 +311,51 @@ impl Display for RunCommands {
         )?;
         writeln!(f, "  Windows: {}", self.win)?;
         writeln!(f, "  Linux: {}", self.linux)?;
-        writeln!(f, "  Mac: {}", self.mac)
+        writeln!(f, "  Mac: {}", self.mac)?;
+        for (selector, command) in self.targets.iter() {
+            writeln!(f, "  {selector}: {command}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RunCommands {
+    fn all(&self) -> impl Iterator<Item = &Command> {
+        let custom = [&self.win, &self.linux, &self.mac]
+            .into_iter()
+            .chain(self.targets.iter().map(|(_, c)| c))
+            .filter_map(|c| match c {
+                RunCommand::Custom(command) => Some(command),
+                _ => None,
+            });
+        self.default.iter().chain(custom)
+    }
+
+    /// Command to run on `target`, `None` if the target is not supported
+    pub fn for_target(&self, target: &Target) -> Option<&Command> {
+        let selected = self.targets.iter().find(|(s, _)| s.matches(target));
+        let os = if let Some((_, command)) = selected {
+            command
+        } else {
+            match target.os.as_str() {
+                "windows" => &self.win,
+                "linux" => &self.linux,
+                "macos" => &self.mac,
+                _ => return self.default.as_ref(),
+            }
+        };
+
+        match os {
+            RunCommand::Null => None,
+            RunCommand::Default => self.default.as_ref(),
+            RunCommand::Custom(command) => Some(command),
+        }
+    }
+
+    /// Command to run on the current platform
+    pub fn for_current_target(&self) -> Option<&Command> {
+        self.for_target(Target::current())
     }
 }
 
@@ -148,11 +380,95 @@ impl Display for RunCommand {
     }
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Clone, Serialize, Default, PartialEq, Eq)]
 pub struct Command {
     pub program: String, // Program to run
     #[serde(default)]
     pub args: Vec<String>, // Arguments to pass to the program
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>, // Environment variables set for the program
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>, // What the command does, shown to the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>, // Seconds after which the program is killed
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub retries: u32, // How many times a failing command is run again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub success_codes: Vec<i32>, // Exit codes meaning success, `[0]` if empty
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub continue_on_error: bool, // Failure doesn't stop the following commands
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<String>, // Globs of files an init step depends on, see `crate::fingerprint`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<String>, // Paths an init step creates, it reruns if any is missing
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+impl Command {
+    /// Creates a process for this command, running in `dir`
+    pub fn to_process(&self, dir: &Path) -> std::process::Command {
+        let mut process = std::process::Command::new(&self.program);
+        process.args(&self.args).envs(&self.env).current_dir(dir);
+        process
+    }
+
+    /// Whether the program exiting with `code` means success
+    pub fn is_success(&self, code: i32) -> bool {
+        if self.success_codes.is_empty() {
+            code == 0
+        } else {
+            self.success_codes.contains(&code)
+        }
+    }
+
+    /// Options changing how the command is run, like `timeout 60s, 2 retries`
+    pub fn options(&self) -> Option<String> {
+        let mut options = vec![];
+        if let Some(timeout) = self.timeout {
+            options.push(format!("timeout {timeout}s"));
+        }
+        match self.retries {
+            0 => {}
+            1 => options.push("1 retry".to_string()),
+            n => options.push(format!("{n} retries")),
+        }
+        if !self.success_codes.is_empty() {
+            let codes: Vec<_> = self.success_codes.iter().map(i32::to_string).collect();
+            options.push(format!("succeeds with {}", codes.join(", ")));
+        }
+        if self.continue_on_error {
+            options.push("continues on error".to_string());
+        }
+
+        (!options.is_empty()).then(|| options.join(", "))
+    }
+
+    /// Returns the command with variables in the program, arguments and env values expanded
+    pub fn resolve(&self, vars: &Vars) -> Result<Command, VarError> {
+        Ok(Command {
+            program: vars.expand(&self.program)?,
+            args: self
+                .args
+                .iter()
+                .map(|a| vars.expand(a))
+                .collect::<Result<_, _>>()?,
+            env: self
+                .env
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), vars.expand(v)?)))
+                .collect::<Result<_, _>>()?,
+            ..self.clone()
+        })
+    }
+
+    fn lint(&self) -> Result<(), VarError> {
+        vars::lint(&self.program)?;
+        self.args.iter().try_for_each(|a| vars::lint(a))?;
+        self.env.values().try_for_each(|v| vars::lint(v))
+    }
 }
 
 impl Display for Command {
@@ -220,6 +536,7 @@ mod tests {
                         .into_iter()
                         .map(str::to_string)
                         .collect(),
+                    ..Default::default()
                 }],
                 ..Default::default()
             },
@@ -230,6 +547,7 @@ mod tests {
                         .into_iter()
                         .map(str::to_string)
                         .collect(),
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -237,9 +555,11 @@ mod tests {
                 global: vec![Command {
                     program: "cargo".to_string(),
                     args: ["--version"].into_iter().map(str::to_string).collect(),
+                    ..Default::default()
                 }],
                 ..Default::default()
             },
+            ..Default::default()
         };
 
         assert_eq!(package_yaml, package);
@@ -291,6 +611,7 @@ mod tests {
                         .into_iter()
                         .map(str::to_string)
                         .collect(),
+                    ..Default::default()
                 }],
                 ..Default::default()
             },
@@ -301,6 +622,7 @@ mod tests {
                         .into_iter()
                         .map(str::to_string)
                         .collect(),
+                    ..Default::default()
                 }),
                 win: RunCommand::Custom(Command {
                     program: "cargo".to_string(),
@@ -308,6 +630,7 @@ mod tests {
                         .into_iter()
                         .map(str::to_string)
                         .collect(),
+                    ..Default::default()
                 }),
                 ..Default::default()
             },
@@ -315,11 +638,90 @@ mod tests {
                 global: vec![Command {
                     program: "cargo".to_string(),
                     args: ["--version"].into_iter().map(str::to_string).collect(),
+                    ..Default::default()
                 }],
                 ..Default::default()
             },
+            ..Default::default()
         };
 
         assert_eq!(package_yaml, Some(package));
     }
+
+    #[test]
+    fn target_selectors() {
+        let yaml = r#"
+            name: Test Package
+            description: This is a test package
+            authors: []
+            init:
+              global:
+                - program: fetch
+              linux:
+                - program: linux
+              cfg(target_arch = "aarch64"):
+                - program: arm
+              cfg(distro_like = "debian"):
+                - program: apt
+            run:
+              default:
+                program: run
+              cfg(target_os = "freebsd"): null
+              cfg(all(linux, target_arch = "aarch64")): !custom
+                program: run-arm"#;
+
+        let package: Package = from_yaml(yaml).unwrap();
+        let pi = Target {
+            os: "linux".to_string(),
+            arch: "aarch64".to_string(),
+            distro: Some("debian".to_string()),
+            ..Default::default()
+        };
+        let bsd = Target {
+            os: "freebsd".to_string(),
+            ..Default::default()
+        };
+
+        let init: Vec<_> = package.init.for_target(&pi).map(|c| &c.program).collect();
+        assert_eq!(init, ["fetch", "linux", "arm", "apt"]);
+        assert_eq!(package.run.for_target(&pi).unwrap().program, "run-arm");
+        assert!(package.run.for_target(&bsd).is_none());
+
+        // Selectors survive a round trip in their original order
+        let again: Package = from_yaml(&serde_yaml::to_string(&package).unwrap()).unwrap();
+        assert_eq!(again, package);
+
+        assert!(from_yaml::<Package>(&yaml.replace("distro_like", "distro_kind")).is_err());
+    }
+
+    #[test]
+    fn lifecycle_hooks() {
+        let yaml = r#"
+            name: Test Package
+            description: This is a test package
+            authors: []
+            run:
+              default:
+                program: run
+            pre_run:
+              global:
+                - program: migrate
+            uninstall:
+              global:
+                - program: rm
+                  args: ["${RUN_THAT_HOME}/cache"]"#;
+
+        let package: Package = from_yaml(yaml).unwrap();
+        assert_eq!(package.pre_run.global[0].program, "migrate");
+        assert_eq!(package.uninstall.global[0].args, ["${RUN_THAT_HOME}/cache"]);
+        assert!(package.post_run.is_empty() && package.post_update.is_empty());
+        assert!(package.lint().is_ok());
+
+        // Hooks are linted like the other command sets
+        let package: Package = from_yaml(&yaml.replace("RUN_THAT_HOME", "HOME")).unwrap();
+        assert_eq!(package.lint(), Err(VarError::Unknown("HOME".to_string())));
+
+        let yaml = serde_yaml::to_string(&package).unwrap();
+        assert!(!yaml.contains("post_run"));
+    }
 }
@@ -1,21 +1,30 @@
-use git2::Repository;
+use crate::{
+    archive::{fetch_and_unpack, is_url, ArchiveKind},
+    cache::Cache,
+    config::Config,
+    manifest::{find_manifest_with, ManifestFormat},
+    semver::{newest_matching, VersionReq},
+};
+use git2::{
+    build::{CloneLocal, RepoBuilder},
+    BranchType, Object, Reference, Repository,
+};
 use lazy_regex::regex;
-use std::path::{Path, PathBuf};
-
-const DEFAULT_DOMAIN: &str = "github.com";
-
-mod errors {
-    use std::fmt::Display;
+use path_absolutize::Absolutize;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
 
+pub mod errors {
     use thiserror::Error;
 
     #[derive(Debug, Error)]
-    pub struct InvalidGitAddress;
-
-    impl Display for InvalidGitAddress {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "Invalid git address")
-        }
+    #[error("invalid git address `{address}`")]
+    pub struct InvalidGitAddress {
+        pub address: String,
+        pub suggestion: Option<String>, // The address written the way we understand it
     }
 
     #[derive(Debug, Error)]
@@ -24,6 +33,12 @@ mod errors {
         CannotClone(#[from] git2::Error),
         #[error("IO error")]
         Io(#[from] std::io::Error),
+        #[error("Cannot get repository from cache")]
+        Cache(#[from] crate::cache::CacheError),
+        #[error("Cannot get archive")]
+        Archive(#[from] crate::archive::ArchiveError),
+        #[error("No version tag matches `{0}`")]
+        NoMatchingTag(crate::semver::VersionReq),
     }
 }
 
@@ -33,9 +48,21 @@ pub struct GitSource {
     user: String,
     name: String,
     spec: Option<GitSpecifier>,
+    url: String, // Where the repo is fetched from, after the configured rewrites
 }
 
 impl GitSource {
+    /// Parses an address like `user/name@tag`, see [`Config`] for the shorthands and rewrites
+    pub fn new(address: String, config: &Config) -> Result<Self, errors::InvalidGitAddress> {
+        match parse_git_address(&address, config) {
+            Some(source) => Ok(source),
+            None => Err(errors::InvalidGitAddress {
+                suggestion: suggest_git_address(&address, config),
+                address,
+            }),
+        }
+    }
+
     pub fn domain(&self) -> &str {
         &self.domain
     }
@@ -51,112 +78,515 @@ impl GitSource {
     pub fn spec(&self) -> Option<&GitSpecifier> {
         self.spec.as_ref()
     }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Resolves a version range to the newest matching tag of the remote.
+    ///
+    /// Returns the source pinned to that tag and the tag, `None` if the specifier isn't a range.
+    pub fn resolve(
+        &self,
+        cache: &Cache,
+    ) -> Result<Option<(GitSource, String)>, errors::GitCloneError> {
+        let Some(GitSpecifier::Range(req)) = &self.spec else {
+            return Ok(None);
+        };
+
+        let repo = Repository::open_bare(cache.mirror(&self.url)?)?;
+        let tag = matching_tag(&repo, req)?;
+        let source = GitSource {
+            spec: Some(GitSpecifier::Tag(tag.clone())),
+            ..self.clone()
+        };
+        Ok(Some((source, tag)))
+    }
+
+    /// Directory of the package relative to the repos directory.
+    ///
+    /// Each specifier is installed next to the others, like `user/name@v1`, see [`split_version`].
+    pub fn id(&self) -> String {
+        let mut user = self.user();
+        // We can't have user named "_local" because it's reserved for local packages
+        if user == "_local" {
+            user = "__local";
+        }
+
+        let version = self.spec.as_ref().map(ToString::to_string);
+        version_id(&format!(
+            "{user}/{}{}",
+            self.name,
+            version.unwrap_or_default()
+        ))
+    }
+}
+
+impl Display for GitSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}/{}", self.domain, self.user, self.name)?;
+        match &self.spec {
+            Some(spec) => write!(f, "{spec}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Versions of a package have ids like `user/name@v1`, `user/name$branch` or `user/name#commit`
+const VERSION_MARKS: [char; 3] = ['@', '$', '#'];
+
+/// Id of a package version as written in an address, with `/` of a branch like `feature/foo`
+/// replaced by `+`, so every version is one directory
+pub fn version_id(name: &str) -> String {
+    match name.find(VERSION_MARKS) {
+        Some(i) => format!("{}{}", &name[..i], name[i..].replace('/', "+")),
+        None => name.to_string(),
+    }
+}
+
+/// Splits an id like `user/name@v1` into the package, `user/name`, and its version, `@v1`.
+///
+/// Local and archive packages have no versions.
+pub fn split_version(id: &str) -> (&str, Option<&str>) {
+    if id.starts_with("_local/") || id.starts_with("_archive/") {
+        return (id, None);
+    }
+
+    let start = id.rfind('/').map_or(0, |i| i + 1);
+    match id[start..].find(VERSION_MARKS) {
+        Some(i) => (&id[..start + i], Some(&id[start + i..])),
+        None => (id, None),
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum GitSpecifier {
     Branch(String),
     Tag(String),
+    Range(VersionReq), // Newest tag matching it, see [`VersionReq`]
     Commit(String),
 }
 
-fn parse_git_address(addr: impl AsRef<str>) -> Option<GitSource> {
+impl Display for GitSpecifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GitSpecifier::Branch(b) => write!(f, "${b}"),
+            GitSpecifier::Tag(t) => write!(f, "@{t}"),
+            GitSpecifier::Range(r) => write!(f, "@{r}"),
+            GitSpecifier::Commit(c) => write!(f, "#{c}"),
+        }
+    }
+}
+
+impl GitSpecifier {
+    /// Parses the part of an address after `$`, `@` or `#`.
+    ///
+    /// Branches and tags can be any valid ref name, like `feature/foo` or `v1.2.0`,
+    /// commits are 4 to 40 hex digits.
+    fn parse(kind: char, spec: &str) -> Option<Self> {
+        match kind {
+            '$' => Reference::is_valid_name(&format!("refs/heads/{spec}"))
+                .then(|| GitSpecifier::Branch(spec.to_string())),
+            '@' => match VersionReq::parse(spec) {
+                Some(req) => Some(GitSpecifier::Range(req)),
+                None => Reference::is_valid_name(&format!("refs/tags/{spec}"))
+                    .then(|| GitSpecifier::Tag(spec.to_string())),
+            },
+            '#' => ((4..=40).contains(&spec.len()) && spec.bytes().all(|b| b.is_ascii_hexdigit()))
+                .then(|| GitSpecifier::Commit(spec.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Parses `[domain:]user/name[spec]`, with shorthands, the default domain and URL rewrites from `config`.
+///
+/// `@latest`, `@^1.2` and `@~0.4` are version ranges, see [`VersionReq`].
+fn parse_git_address(addr: impl AsRef<str>, config: &Config) -> Option<GitSource> {
     let r = regex!(
-        "^((?P<domain>(([a-zA-Z]{1})|([a-zA-Z]{1}[a-zA-Z]{1})|([a-zA-Z]{1}[0-9]{1})|([0-9]{1}[a-zA-Z]{1})|([a-zA-Z0-9][a-zA-Z0-9-_]{1,61}[a-zA-Z0-9]))\\.([a-zA-Z]{2,6}|[a-zA-Z0-9-]{2,30}\\.[a-zA-Z]{2,3})):)?(?P<user>[\\w_-]+)/(?P<name>[\\w_-]+)((?P<spectype>[@#$])(?P<spec>\\w+))?$"
+        "^((?P<domain>(([a-zA-Z]{1})|([a-zA-Z]{1}[a-zA-Z]{1})|([a-zA-Z]{1}[0-9]{1})|([0-9]{1}[a-zA-Z]{1})|([a-zA-Z0-9][a-zA-Z0-9-_]{1,61}[a-zA-Z0-9]))\\.([a-zA-Z]{2,6}|[a-zA-Z0-9-]{2,30}\\.[a-zA-Z]{2,3})):)?(?P<user>[\\w_-]+)/(?P<name>[\\w_-]+)((?P<spectype>[@#$])(?P<spec>.+))?$"
     );
 
-    let caps = r.captures(addr.as_ref())?;
+    let addr = config.expand_shorthand(addr.as_ref());
+    let caps = r.captures(&addr)?;
+    let domain = caps
+        .name("domain")
+        .map(|d| d.as_str())
+        .unwrap_or(&config.default_domain)
+        .to_string();
+    let user = caps.name("user").unwrap().as_str().to_string();
+    let name = caps.name("name").unwrap().as_str().to_string();
+
+    let spec = match (caps.name("spectype"), caps.name("spec")) {
+        (Some(kind), Some(spec)) => Some(GitSpecifier::parse(
+            kind.as_str().chars().next()?,
+            spec.as_str(),
+        )?),
+        _ => None,
+    };
 
     Some(GitSource {
-        domain: caps
-            .name("domain")
-            .map(|d| d.as_str())
-            .unwrap_or(DEFAULT_DOMAIN)
-            .to_string(),
-        user: caps.name("user").unwrap().as_str().to_string(),
-        name: caps.name("name").unwrap().as_str().to_string(),
-        spec: match caps.name("spectype") {
-            Some(t) => match t.as_str() {
-                "$" => Some(GitSpecifier::Branch(
-                    caps.name("spec").unwrap().as_str().to_string(),
-                )),
-                "@" => Some(GitSpecifier::Tag(
-                    caps.name("spec").unwrap().as_str().to_string(),
-                )),
-                "#" => Some(GitSpecifier::Commit(
-                    caps.name("spec").unwrap().as_str().to_string(),
-                )),
-                _ => unreachable!(),
-            },
-            None => None,
-        },
+        url: config.rewrite_url(&format!("https://{domain}/{user}/{name}")),
+        domain,
+        user,
+        name,
+        spec,
     })
 }
 
-fn clone_repo(
+/// Address for a URL or SSH remote pasted instead of one, `https://host/user/name.git` to `host:user/name`
+fn suggest_git_address(addr: &str, config: &Config) -> Option<String> {
+    let rest = addr
+        .strip_prefix("https://")
+        .or_else(|| addr.strip_prefix("http://"))
+        .or_else(|| addr.strip_prefix("git@"))
+        .unwrap_or(addr);
+    let rest = rest.trim_end_matches('/');
+    let rest = rest.strip_suffix(".git").unwrap_or(rest);
+
+    let (domain, path) = rest.split_once(['/', ':'])?;
+    let suggestion = format!("{domain}:{path}");
+    parse_git_address(&suggestion, config).map(|_| suggestion)
+}
+
+/// Clones the repository into `path`, which must not exist, through its mirror in `cache`.
+///
+/// Objects are hardlinked from the mirror, so versions of a package installed side by side
+/// share one object store.
+pub fn clone_repo(
+    source: &GitSource,
+    path: impl AsRef<Path>,
+    cache: &Cache,
+) -> Result<(), errors::GitCloneError> {
+    let mirror = cache.mirror(&source.url())?;
+    let repo = RepoBuilder::new()
+        .clone_local(CloneLocal::Local) // Hardlinks objects from the mirror
+        .clone(&mirror.to_string_lossy(), path.as_ref())?;
+    if let Some(spec) = source.spec() {
+        checkout_spec(&repo, spec)?;
+    }
+
+    Ok(())
+}
+
+fn checkout_spec(repo: &Repository, spec: &GitSpecifier) -> Result<(), errors::GitCloneError> {
+    let commit = match spec {
+        GitSpecifier::Branch(b) => repo.revparse_single(&format!("refs/remotes/origin/{b}"))?,
+        GitSpecifier::Tag(t) => repo.revparse_single(&format!("refs/tags/{t}"))?,
+        GitSpecifier::Range(r) => {
+            repo.revparse_single(&format!("refs/tags/{}", matching_tag(repo, r)?))?
+        }
+        GitSpecifier::Commit(c) => repo.revparse_single(c)?,
+    }
+    .peel_to_commit()?;
+
+    repo.checkout_tree(
+        commit.as_object(),
+        Some(git2::build::CheckoutBuilder::new().force()),
+    )?;
+
+    match spec {
+        GitSpecifier::Branch(b) => {
+            if repo.find_branch(b, BranchType::Local).is_err() {
+                let mut branch = repo.branch(b, &commit, false)?;
+                branch.set_upstream(Some(&format!("origin/{b}")))?;
+            }
+            repo.set_head(&format!("refs/heads/{b}"))?
+        }
+        _ => repo.set_head_detached(commit.id())?,
+    }
+
+    Ok(())
+}
+
+/// Newest tag of the repository matching the version range
+fn matching_tag(repo: &Repository, req: &VersionReq) -> Result<String, errors::GitCloneError> {
+    let names = repo.tag_names(None)?;
+    newest_matching(req, names.iter().flatten())
+        .map(str::to_string)
+        .ok_or_else(|| errors::GitCloneError::NoMatchingTag(req.clone()))
+}
+
+/// Manifest of a git package, read from its mirror without a checkout
+#[derive(Debug, Clone)]
+pub struct RemoteManifest {
+    pub commit: String,
+    pub file: &'static str, // Like `run.yml`
+    pub format: ManifestFormat,
+    pub text: String,
+}
+
+/// Fetches the mirror of the repository and reads the manifest at the source's specifier
+/// straight from its blobs, `None` if the commit has no manifest
+pub fn read_remote_manifest(
     source: &GitSource,
+    cache: &Cache,
+) -> Result<Option<RemoteManifest>, errors::GitCloneError> {
+    let mirror = cache.mirror(&source.url())?;
+    let repo = Repository::open_bare(mirror)?;
+    // Branches of the mirror are its local branches, not remote ones like in a checkout
+    let commit = match source.spec() {
+        Some(GitSpecifier::Branch(b)) => repo.revparse_single(&format!("refs/heads/{b}"))?,
+        Some(GitSpecifier::Tag(t)) => repo.revparse_single(&format!("refs/tags/{t}"))?,
+        Some(GitSpecifier::Range(r)) => {
+            repo.revparse_single(&format!("refs/tags/{}", matching_tag(&repo, r)?))?
+        }
+        Some(GitSpecifier::Commit(c)) => repo.revparse_single(c)?,
+        None => default_branch(&repo)?,
+    }
+    .peel_to_commit()?;
+
+    let tree = commit.tree()?;
+    let manifest = find_manifest_with(|file| {
+        let blob = tree
+            .get_name(file)?
+            .to_object(&repo)
+            .ok()?
+            .peel_to_blob()
+            .ok()?;
+        String::from_utf8(blob.content().to_vec()).ok()
+    });
+    Ok(manifest.map(|(file, format, text)| RemoteManifest {
+        commit: commit.id().to_string(),
+        file,
+        format,
+        text,
+    }))
+}
+
+/// Tip of the branch a clone of the mirror would check out
+fn default_branch(repo: &Repository) -> Result<Object<'_>, git2::Error> {
+    repo.revparse_single("HEAD")
+        .or_else(|_| repo.revparse_single("refs/heads/main"))
+        .or_else(|_| repo.revparse_single("refs/heads/master"))
+}
+
+/// Id of the commit checked out in the repository at `path`
+pub fn head_commit(path: impl AsRef<Path>) -> Option<String> {
+    let repo = Repository::open(path).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(commit.id().to_string())
+}
+
+/// Fetches the remote of the checkout at `path` and returns the commit the upstream of its
+/// branch points to.
+///
+/// With `url`, its mirror in `cache` is refreshed first and becomes the remote of the checkout.
+/// Packages pinned to a tag or commit, or with a detached head, return `None`.
+pub fn fetch_upstream(
+    path: impl AsRef<Path>,
+    cache: &Cache,
+    url: Option<&str>,
+) -> Result<Option<String>, errors::GitCloneError> {
+    let repo = Repository::open(path)?;
+    if let Some(url) = url {
+        let mirror = cache.mirror(url)?;
+        repo.remote_set_url("origin", &mirror.to_string_lossy())?;
+    }
+
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Ok(None);
+    }
+
+    let branch = git2::Branch::wrap(head);
+    let upstream = branch.upstream()?;
+    let remote_ref = upstream.get().name().unwrap_or_default().to_string();
+
+    repo.find_remote("origin")?.fetch::<&str>(&[], None, None)?;
+    let commit = repo.revparse_single(&remote_ref)?.peel_to_commit()?;
+    Ok(Some(commit.id().to_string()))
+}
+
+/// Fetches the tags of the mirror of `url` into the checkout at `path` and returns the commit
+/// `tag` points to
+pub fn fetch_tag(
     path: impl AsRef<Path>,
-    force: bool,
+    cache: &Cache,
+    url: &str,
+    tag: &str,
+) -> Result<String, errors::GitCloneError> {
+    let repo = Repository::open(path)?;
+    let mirror = cache.mirror(url)?;
+    repo.remote_set_url("origin", &mirror.to_string_lossy())?;
+    repo.find_remote("origin")?
+        .fetch(&["+refs/tags/*:refs/tags/*"], None, None)?;
+
+    let commit = repo
+        .revparse_single(&format!("refs/tags/{tag}"))?
+        .peel_to_commit()?;
+    Ok(commit.id().to_string())
+}
+
+/// Checks out `commit` in the repository at `path`, moving the checked out branch if there's one.
+///
+/// Returns whether the checkout changed.
+pub fn checkout_commit(
+    path: impl AsRef<Path>,
+    commit: &str,
 ) -> Result<bool, errors::GitCloneError> {
-    let mut user = source.user();
-    // We can't have user named "_local" because it's reserved for local packages
-    if user == "_local" {
-        user = "__local";
+    let repo = Repository::open(path)?;
+    let commit = repo.revparse_single(commit)?.peel_to_commit()?;
+    let head = repo.head()?;
+    if head.peel_to_commit()?.id() == commit.id() {
+        return Ok(false);
     }
 
-    let path = path.as_ref().join(user).join(source.name());
+    repo.checkout_tree(
+        commit.as_object(),
+        Some(git2::build::CheckoutBuilder::new().force()),
+    )?;
+    if head.is_branch() {
+        repo.head()?.set_target(commit.id(), "run-that: checkout")?;
+    } else {
+        repo.set_head_detached(commit.id())?;
+    }
+    Ok(true)
+}
 
-    if path.exists() {
-        if force {
-            std::fs::remove_dir_all(&path)?;
+/// Copies the directory recursively, symlinks are copied as links where the platform has them
+pub(crate) fn copy_dir(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if cfg!(unix) && file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, target)?;
         } else {
-            return Ok(false);
+            std::fs::copy(entry.path(), target)?;
         }
     }
 
-    let url = format!(
-        "https://{}/{}/{}",
-        source.domain(),
-        source.user(),
-        source.name()
-    );
+    Ok(())
+}
+
+/// Release archive, either a local file or an HTTP(S) URL
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ArchiveSource {
+    location: String,
+    sha256: Option<String>,
+}
 
-    Repository::clone(&url, &path)?;
+impl ArchiveSource {
+    pub fn location(&self) -> &str {
+        &self.location
+    }
 
-    Ok(true)
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
 }
 
+#[derive(Debug, Clone)]
 enum PackageSourceInner {
     Git(GitSource),
     Local(PathBuf),
+    Archive(ArchiveSource),
 }
 
+#[derive(Debug, Clone)]
 pub struct PackageSource {
     inner: PackageSourceInner,
 }
 
 impl PackageSource {
-    pub fn new_git(address: String) -> Result<Self, errors::InvalidGitAddress> {
-        if let Some(source) = parse_git_address(address) {
-            Ok(Self {
-                inner: PackageSourceInner::Git(source),
-            })
-        } else {
-            Err(errors::InvalidGitAddress)
-        }
+    pub fn new_git(address: String, config: &Config) -> Result<Self, errors::InvalidGitAddress> {
+        Ok(Self {
+            inner: PackageSourceInner::Git(GitSource::new(address, config)?),
+        })
     }
 
     pub fn new_local(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
         Self {
-            inner: PackageSourceInner::Local(path.as_ref().to_path_buf()),
+            inner: PackageSourceInner::Local(
+                path.absolutize()
+                    .map(Into::into)
+                    .unwrap_or_else(|_| path.to_path_buf()),
+            ),
+        }
+    }
+
+    /// Creates an archive source, `location` is either a path or an HTTP(S) URL.
+    ///
+    /// Returns `None` if `location` doesn't look like a supported archive.
+    pub fn new_archive(location: impl AsRef<str>, sha256: Option<String>) -> Option<Self> {
+        let location = location.as_ref();
+        ArchiveKind::detect(location)?;
+
+        let location = if is_url(location) {
+            location.to_string()
+        } else {
+            let path = Path::new(location);
+            path.absolutize()
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| location.to_string())
+        };
+
+        Some(Self {
+            inner: PackageSourceInner::Archive(ArchiveSource { location, sha256 }),
+        })
+    }
+
+    pub fn git(&self) -> Option<&GitSource> {
+        match &self.inner {
+            PackageSourceInner::Git(source) => Some(source),
+            _ => None,
+        }
+    }
+
+    pub fn local(&self) -> Option<&Path> {
+        match &self.inner {
+            PackageSourceInner::Local(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn archive(&self) -> Option<&ArchiveSource> {
+        match &self.inner {
+            PackageSourceInner::Archive(source) => Some(source),
+            _ => None,
+        }
+    }
+
+    /// Directory of the package relative to the repos directory
+    pub fn id(&self) -> String {
+        match &self.inner {
+            PackageSourceInner::Git(source) => source.id(),
+            PackageSourceInner::Local(path) => format!(
+                "_local/{}",
+                path.file_name()
+                    .map(|n| n.to_string_lossy())
+                    .unwrap_or_default()
+            ),
+            PackageSourceInner::Archive(source) => {
+                // Archives with the same file name from different places don't share a directory
+                let hash = format!("{:x}", Sha256::digest(source.location.as_bytes()));
+                format!(
+                    "_archive/{}-{}",
+                    ArchiveKind::stem(&source.location),
+                    &hash[..8]
+                )
+            }
         }
     }
 
-    fn put_to(&self, path: impl AsRef<Path>) -> Result<(), std::io::Error> {
+    /// Puts the package into `path`, which must not exist
+    pub fn put_to(&self, path: &Path, cache: &Cache) -> Result<(), errors::GitCloneError> {
         match &self.inner {
-            PackageSourceInner::Git(_) => todo!(),
-            PackageSourceInner::Local(p) => {
-                std::fs::copy(p, path)?;
+            PackageSourceInner::Git(source) => clone_repo(source, path, cache)?,
+            PackageSourceInner::Local(p) => copy_dir(p, path)?,
+            PackageSourceInner::Archive(source) => {
+                let parent = path.parent().unwrap_or(Path::new("."));
+                std::fs::create_dir_all(parent)?;
+                // Unpack next to the final directory, so it can be just renamed
+                let tmp = tempfile::Builder::new().prefix(".tmp").tempdir_in(parent)?;
+                let root = fetch_and_unpack(&source.location, source.sha256(), tmp.path())?;
+                std::fs::rename(root, path)?;
             }
         }
 
@@ -164,59 +594,172 @@ impl PackageSource {
     }
 }
 
+impl Display for PackageSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.inner {
+            PackageSourceInner::Git(source) => write!(f, "{source}"),
+            PackageSourceInner::Local(path) => write!(f, "{}", path.display()),
+            PackageSourceInner::Archive(source) => write!(f, "{}", source.location),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_git_address() {
+        let config = Config::default();
         assert_eq!(
-            parse_git_address("github.com:ur-fault/run-that@tag"),
+            parse_git_address("github.com:ur-fault/run-that@tag", &config),
             Some(GitSource {
                 domain: "github.com".to_string(),
                 user: "ur-fault".to_string(),
                 name: "run-that".to_string(),
                 spec: Some(GitSpecifier::Tag("tag".to_string())),
+                url: "https://github.com/ur-fault/run-that".to_string(),
             })
         );
 
         assert_eq!(
-            parse_git_address("gitlab.com:ur-fault/run-that#abc123"),
+            parse_git_address("gitlab.com:ur-fault/run-that#abc123", &config),
             Some(GitSource {
                 domain: "gitlab.com".to_string(),
                 user: "ur-fault".to_string(),
                 name: "run-that".to_string(),
                 spec: Some(GitSpecifier::Commit("abc123".to_string())),
+                url: "https://gitlab.com/ur-fault/run-that".to_string(),
             })
         );
         assert_eq!(
-            parse_git_address("codeberg.com:ur-fault/lil-game$branch"),
+            parse_git_address("codeberg.com:ur-fault/lil-game$branch", &config),
             Some(GitSource {
                 domain: "codeberg.com".to_string(),
                 user: "ur-fault".to_string(),
                 name: "lil-game".to_string(),
                 spec: Some(GitSpecifier::Branch("branch".to_string())),
+                url: "https://codeberg.com/ur-fault/lil-game".to_string(),
             })
         );
         assert_eq!(
-            parse_git_address("codeberg.com:ur-fault/lil-game"),
+            parse_git_address("codeberg.com:ur-fault/lil-game", &config),
             Some(GitSource {
                 domain: "codeberg.com".to_string(),
                 user: "ur-fault".to_string(),
                 name: "lil-game".to_string(),
                 spec: None,
+                url: "https://codeberg.com/ur-fault/lil-game".to_string(),
             })
         );
         assert_eq!(
-            parse_git_address("ur-fault/lil-game$asd"),
+            parse_git_address("ur-fault/lil-game$asd", &config),
             Some(GitSource {
-                domain: DEFAULT_DOMAIN.to_string(),
+                domain: "github.com".to_string(),
                 user: "ur-fault".to_string(),
                 name: "lil-game".to_string(),
                 spec: Some(GitSpecifier::Branch("asd".to_string())),
+                url: "https://github.com/ur-fault/lil-game".to_string(),
             })
         );
-        assert_eq!(parse_git_address(":ur-fault/lil-game$asd"), None);
-        assert_eq!(parse_git_address("ur-fault/lil-game$"), None);
+        let source = parse_git_address("cb:ur-fault/lil-game", &config).unwrap();
+        assert_eq!(source.url(), "https://codeberg.org/ur-fault/lil-game");
+        assert_eq!(parse_git_address(":ur-fault/lil-game$asd", &config), None);
+        assert_eq!(parse_git_address("ur-fault/lil-game$", &config), None);
+    }
+
+    #[test]
+    fn ref_grammar() {
+        let config = Config::default();
+        let spec = |address: &str| parse_git_address(address, &config).map(|s| s.spec);
+        assert_eq!(
+            spec("u/r@v1.2.0"),
+            Some(Some(GitSpecifier::Tag("v1.2.0".to_string())))
+        );
+        assert_eq!(
+            spec("u/r$feature/foo"),
+            Some(Some(GitSpecifier::Branch("feature/foo".to_string())))
+        );
+        assert_eq!(
+            spec("u/r@^1.2"),
+            Some(Some(GitSpecifier::Range(
+                VersionReq::parse("^1.2").unwrap()
+            )))
+        );
+        assert_eq!(
+            spec("u/r@latest"),
+            Some(Some(GitSpecifier::Range(VersionReq::Latest)))
+        );
+        assert_eq!(
+            spec("u/r@ü"),
+            Some(Some(GitSpecifier::Tag("ü".to_string())))
+        );
+        for invalid in [
+            "u/r$foo..bar",
+            "u/r@v1.lock",
+            "u/r$a b",
+            "u/r#xyz1",
+            "u/r@^1.x",
+        ] {
+            assert_eq!(spec(invalid), None, "{invalid}");
+        }
+
+        let source = parse_git_address("u/r@~0.4", &config).unwrap();
+        assert_eq!(source.to_string(), "github.com:u/r@~0.4");
+    }
+
+    #[test]
+    fn address_suggestions() {
+        let config = Config::default();
+        assert_eq!(
+            suggest_git_address("https://github.com/ur-fault/run-that.git", &config).as_deref(),
+            Some("github.com:ur-fault/run-that")
+        );
+        assert_eq!(
+            suggest_git_address("git@codeberg.org:user/tool.git", &config).as_deref(),
+            Some("codeberg.org:user/tool")
+        );
+        assert_eq!(suggest_git_address("tool", &config), None);
+
+        let error = PackageSource::new_git("tool".to_string(), &config).unwrap_err();
+        assert_eq!(error.to_string(), "invalid git address `tool`");
+    }
+
+    #[test]
+    fn archive_ids() {
+        let id = |location: &str| PackageSource::new_archive(location, None).unwrap().id();
+        let (first, second) = (
+            id("https://a.example/tool.tar.gz"),
+            id("https://b.example/tool.tar.gz"),
+        );
+        assert!(first.starts_with("_archive/tool-"), "{first}");
+        assert_eq!(first.len(), "_archive/tool-".len() + 8);
+        assert_ne!(first, second);
+        assert_eq!(first, id("https://a.example/tool.tar.gz"));
+    }
+
+    #[test]
+    fn remote_manifest() {
+        use crate::test_support::{commit, tag, Remotes};
+
+        let remotes = Remotes::new();
+        let repo = remotes.repo("r", "name: v1");
+        let first = tag(&repo, "v1");
+        commit(&repo, "run.yml", "name: v2");
+
+        let (config, cache) = (
+            &remotes.config,
+            Cache::new(remotes.dir.path().join("cache"), false),
+        );
+        let read = |address: &str| {
+            let source = GitSource::new(address.to_string(), config).unwrap();
+            read_remote_manifest(&source, &cache).unwrap().unwrap()
+        };
+
+        let latest = read("u/r");
+        assert_eq!((latest.file, latest.text.as_str()), ("run.yml", "name: v2"));
+        let tagged = read("u/r@v1");
+        assert_eq!(tagged.text, "name: v1");
+        assert_eq!(tagged.commit, first);
     }
 }
@@ -0,0 +1,273 @@
+use std::{cmp::Ordering, fmt::Display};
+
+/// Version read from a tag like `v1.2.3`, `1.2` or `1.0.0-rc.1`.
+///
+/// Missing minor and patch numbers are 0, build metadata after `+` is ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<String>, // Dot separated pre-release identifiers, empty for releases
+}
+
+impl Version {
+    /// Parses a tag, `None` if it isn't a version
+    pub fn parse(tag: &str) -> Option<Self> {
+        let version = tag.strip_prefix(['v', 'V']).unwrap_or(tag);
+        let version = version.split_once('+').map_or(version, |(v, _)| v);
+        let (numbers, pre) = match version.split_once('-') {
+            Some((numbers, pre)) if !pre.is_empty() => {
+                (numbers, pre.split('.').map(str::to_string).collect())
+            }
+            Some(_) => return None,
+            None => (version, vec![]),
+        };
+
+        let mut parts = numbers.split('.').map(|n| {
+            n.parse::<u64>()
+                .ok()
+                .filter(|_| n.bytes().all(|b| b.is_ascii_digit()))
+        });
+        let major = parts.next()??;
+        let minor = parts.next().unwrap_or(Some(0))?;
+        let patch = parts.next().unwrap_or(Some(0))?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            major,
+            minor,
+            patch,
+            pre,
+        })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                // A release is newer than its pre-releases
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => compare_pre(&self.pre, &other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Numeric identifiers compare as numbers and are older than alphanumeric ones
+fn compare_pre(a: &[String], b: &[String]) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    a.len().cmp(&b.len())
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if !self.pre.is_empty() {
+            write!(f, "-{}", self.pre.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+/// Range of versions a tag specifier like `@^1.2`, `@~0.4` or `@latest` asks for.
+///
+/// `^` allows changes that don't modify the leftmost non-zero number, `~` allows patch
+/// changes, or minor ones if only the major number is given. Pre-releases never match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionReq {
+    Caret(Partial),
+    Tilde(Partial),
+    Latest,
+}
+
+/// Version with the minor and patch numbers optional, like `1.2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partial {
+    pub major: u64,
+    pub minor: Option<u64>,
+    pub patch: Option<u64>,
+}
+
+impl VersionReq {
+    /// Parses a range, `None` for anything else, like a plain tag
+    pub fn parse(spec: &str) -> Option<Self> {
+        if spec == "latest" {
+            return Some(VersionReq::Latest);
+        }
+
+        let (kind, version): (fn(Partial) -> Self, _) = if let Some(rest) = spec.strip_prefix('^') {
+            (VersionReq::Caret, rest)
+        } else if let Some(rest) = spec.strip_prefix('~') {
+            (VersionReq::Tilde, rest)
+        } else {
+            return None;
+        };
+        let mut parts = version.split('.').map(|n| {
+            n.parse::<u64>()
+                .ok()
+                .filter(|_| n.bytes().all(|b| b.is_ascii_digit()))
+        });
+        let major = parts.next()??;
+        let minor = parts.next().map(|n| n.ok_or(())).transpose().ok()?;
+        let patch = parts.next().map(|n| n.ok_or(())).transpose().ok()?;
+        if parts.next().is_some() || (minor.is_none() && patch.is_some()) {
+            return None;
+        }
+
+        Some(kind(Partial {
+            major,
+            minor,
+            patch,
+        }))
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        if version.is_prerelease() {
+            return false;
+        }
+
+        let (VersionReq::Caret(p) | VersionReq::Tilde(p)) = self else {
+            return true;
+        };
+        let v = version;
+        if (v.major, v.minor, v.patch) < (p.major, p.minor.unwrap_or(0), p.patch.unwrap_or(0)) {
+            return false;
+        }
+
+        match (self, p.minor, p.patch) {
+            (VersionReq::Caret(_), _, _) if p.major > 0 => v.major == p.major,
+            (VersionReq::Caret(_), Some(0), Some(patch)) => {
+                (v.major, v.minor, v.patch) == (0, 0, patch)
+            }
+            (_, Some(minor), _) => (v.major, v.minor) == (p.major, minor),
+            (_, None, _) => v.major == p.major,
+        }
+    }
+}
+
+impl Display for VersionReq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (op, p) = match self {
+            VersionReq::Latest => return write!(f, "latest"),
+            VersionReq::Caret(p) => ('^', p),
+            VersionReq::Tilde(p) => ('~', p),
+        };
+        write!(f, "{op}{}", p.major)?;
+        for n in [p.minor, p.patch].into_iter().flatten() {
+            write!(f, ".{n}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Newest of `tags` matching `req`, tags that aren't versions are ignored
+pub fn newest_matching<'a>(
+    req: &VersionReq,
+    tags: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    tags.into_iter()
+        .filter_map(|tag| Some((Version::parse(tag)?, tag)))
+        .filter(|(version, _)| req.matches(version))
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, tag)| tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_order() {
+        let v = |tag| Version::parse(tag).unwrap();
+        assert_eq!(v("v1.2.3").to_string(), "1.2.3");
+        assert_eq!(v("2").to_string(), "2.0.0");
+        assert_eq!(v("1.0.0-rc.1+build.5").to_string(), "1.0.0-rc.1");
+        assert!(Version::parse("latest").is_none());
+        assert!(Version::parse("1.2.3.4").is_none());
+        assert!(Version::parse("1.x").is_none());
+
+        let mut tags = [
+            "1.0.0",
+            "1.0.0-rc.2",
+            "1.0.0-rc.10",
+            "0.9.1",
+            "1.0.0-beta",
+            "v1.1",
+        ];
+        tags.sort_by_key(|t| v(t));
+        assert_eq!(
+            tags,
+            [
+                "0.9.1",
+                "1.0.0-beta",
+                "1.0.0-rc.2",
+                "1.0.0-rc.10",
+                "1.0.0",
+                "v1.1"
+            ]
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        let tags = [
+            "v0.3.9",
+            "v0.4.0",
+            "v0.4.7",
+            "v0.5.0",
+            "v1.1.0",
+            "v1.2.0",
+            "v1.9.3",
+            "v2.0.0-rc.1",
+            "nightly",
+        ];
+        let newest = |req: &str| newest_matching(&VersionReq::parse(req).unwrap(), tags);
+        assert_eq!(newest("^1.2"), Some("v1.9.3"));
+        assert_eq!(newest("~1.2"), Some("v1.2.0"));
+        assert_eq!(newest("~0.4"), Some("v0.4.7"));
+        assert_eq!(newest("^0.4"), Some("v0.4.7"));
+        assert_eq!(newest("^0.4.8"), None);
+        assert_eq!(newest("~1"), Some("v1.9.3"));
+        assert_eq!(newest("^0"), Some("v0.5.0"));
+        assert_eq!(newest("latest"), Some("v1.9.3"));
+        assert_eq!(newest("^3"), None);
+
+        assert!(VersionReq::parse("^0.0.3")
+            .unwrap()
+            .matches(&Version::parse("0.0.3").unwrap()));
+        assert!(!VersionReq::parse("^0.0.3")
+            .unwrap()
+            .matches(&Version::parse("0.0.4").unwrap()));
+        assert_eq!(VersionReq::parse("~0.4").unwrap().to_string(), "~0.4");
+        for invalid in ["1.2", "^", "^1.x", "~1.2.3.4", "^v1"] {
+            assert_eq!(VersionReq::parse(invalid), None, "{invalid}");
+        }
+    }
+}
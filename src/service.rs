@@ -0,0 +1,277 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::package::Command;
+
+/// How long `stop` waits after SIGTERM before sending SIGKILL
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum ServiceError {
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("service record is corrupted")]
+    Parse(#[from] serde_yaml::Error),
+    #[error("`{0}` is already running with pid {1}")]
+    AlreadyRunning(String, u32),
+    #[error("`{0}` is not running")]
+    NotRunning(String),
+    #[error("background services are not supported on this platform")]
+    Unsupported,
+}
+
+/// A package running in the background, stored in `MAIN_PATH/services/<id>.yml`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServiceRecord {
+    pub id: String,
+    pub pid: u32, // Also the id of the process group
+    pub started_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<u64>, // Of the process, to tell it from a later one with the same pid
+    pub command: Command,                 // Resolved command including `args`
+    pub args: Vec<String>,                // Arguments given by the user, kept for `restart`
+    pub params: BTreeMap<String, String>, // Parameters given by the user, kept for `restart`
+    pub log: PathBuf,                     // Where the output goes
+}
+
+impl ServiceRecord {
+    pub fn path(dir: &Path, id: &str) -> PathBuf {
+        dir.join(format!("{id}.yml"))
+    }
+
+    pub fn load(dir: &Path, id: &str) -> Result<Option<Self>, ServiceError> {
+        let path = Self::path(dir, id);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = std::fs::File::open(path)?;
+        Ok(Some(serde_yaml::from_reader(file)?))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), ServiceError> {
+        let path = Self::path(dir, &self.id);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn delete(dir: &Path, id: &str) -> Result<(), ServiceError> {
+        let path = Self::path(dir, id);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// All records in `dir`, sorted by id
+    pub fn list(dir: &Path) -> Result<Vec<Self>, ServiceError> {
+        let mut records = vec![];
+        if dir.exists() {
+            find_records(dir, &mut records)?;
+        }
+
+        records.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(records)
+    }
+
+    /// Whether the process group still has a live process.
+    ///
+    /// `false` means the pid file is stale, the service exited or was killed, or the pid
+    /// belongs to another process now.
+    pub fn is_running(&self) -> bool {
+        group_alive(self.pid, self.start_time) && !is_zombie(self.pid)
+    }
+}
+
+fn find_records(dir: &Path, records: &mut Vec<ServiceRecord>) -> Result<(), ServiceError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_records(&path, records)?;
+        } else if path.extension().is_some_and(|e| e == "yml") {
+            let file = std::fs::File::open(&path)?;
+            records.push(serde_yaml::from_reader(file)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Start time of a process in clock ticks after boot, field 22 of `/proc/<pid>/stat`
+#[cfg(target_os = "linux")]
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name in field 2 may contain spaces, fields after it start with the state
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(22 - 3)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn start_time(_: u32) -> Option<u64> {
+    None
+}
+
+/// Whether the group `pgid` is still the one whose leader started at `start_time`.
+///
+/// A pid is not reused while its group exists, so a group whose leader exited is still ours.
+#[cfg(unix)]
+fn same_group(pgid: u32, start_time: Option<u64>) -> bool {
+    match (start_time, self::start_time(pgid)) {
+        (Some(recorded), Some(current)) => recorded == current,
+        _ => true,
+    }
+}
+
+#[cfg(unix)]
+fn signal_group(pgid: u32, start_time: Option<u64>, signal: i32) -> bool {
+    if !same_group(pgid, start_time) {
+        return false;
+    }
+
+    // SAFETY: `kill` has no memory safety requirements
+    unsafe { libc::kill(-(pgid as i32), signal) == 0 }
+}
+
+#[cfg(unix)]
+fn group_alive(pgid: u32, start_time: Option<u64>) -> bool {
+    signal_group(pgid, start_time, 0)
+}
+
+#[cfg(not(unix))]
+fn group_alive(_: u32, _: Option<u64>) -> bool {
+    false
+}
+
+/// Whether the process exited but wasn't reaped yet, which happens when nobody adopts it
+#[cfg(target_os = "linux")]
+fn is_zombie(pid: u32) -> bool {
+    std::fs::read_to_string(format!("/proc/{pid}/stat"))
+        .ok()
+        .and_then(|stat| {
+            let (_, rest) = stat.rsplit_once(')')?;
+            rest.trim_start().chars().next()
+        })
+        .is_some_and(|state| state == 'Z')
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_zombie(_: u32) -> bool {
+    false
+}
+
+/// Sends SIGTERM to the whole process group, then SIGKILL if it's still alive after `timeout`.
+///
+/// Nothing is signalled once the pid belongs to a process started at another time than
+/// `start_time`. Returns `true` if SIGKILL was needed.
+#[cfg(unix)]
+pub fn terminate(
+    pgid: u32,
+    start_time: Option<u64>,
+    timeout: Duration,
+) -> Result<bool, ServiceError> {
+    use std::time::Instant;
+
+    if !signal_group(pgid, start_time, libc::SIGTERM) {
+        return Ok(false);
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if !group_alive(pgid, start_time) || is_zombie(pgid) {
+            // Leftovers of the group that outlived its leader
+            signal_group(pgid, start_time, libc::SIGKILL);
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    signal_group(pgid, start_time, libc::SIGKILL);
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn terminate(_: u32, _: Option<u64>, _: Duration) -> Result<bool, ServiceError> {
+    Err(ServiceError::Unsupported)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_and_terminate_group() {
+        use crate::{
+            executor::{Executor, Local, Sink, Step},
+            logs::{Log, Stage},
+        };
+
+        let tmp = tempfile::tempdir().unwrap();
+        let log = Log::new(tmp.path(), "user/server", Stage::Service);
+
+        // The child ignores SIGTERM, the group must be killed anyway
+        let command = Command {
+            program: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "echo up; trap '' TERM; sleep 30".to_string(),
+            ],
+            ..Default::default()
+        };
+        let step = Local.execute(&Step {
+            command: &command,
+            dir: tmp.path(),
+            stdout: Sink::Null,
+            stderr: Sink::Null,
+            log: Some(&log),
+            detached: true,
+        });
+        let pid = step.pid.unwrap();
+        let started = start_time(pid);
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(group_alive(pid, started));
+
+        // A process started at another time is not ours to signal
+        if let Some(started) = started {
+            assert!(!group_alive(pid, Some(started + 1)));
+            assert!(!terminate(pid, Some(started + 1), Duration::ZERO).unwrap());
+            assert!(group_alive(pid, Some(started)));
+        }
+
+        assert!(terminate(pid, started, Duration::from_millis(300)).unwrap());
+        assert_eq!(std::fs::read_to_string(log.path()).unwrap(), "up\n");
+    }
+
+    #[test]
+    fn records() {
+        let tmp = tempfile::tempdir().unwrap();
+        let record = ServiceRecord {
+            id: "user/server".to_string(),
+            pid: u32::MAX / 2,
+            started_at: 0,
+            start_time: None,
+            command: Command::default(),
+            args: vec![],
+            params: BTreeMap::new(),
+            log: PathBuf::new(),
+        };
+
+        record.save(tmp.path()).unwrap();
+        let records = ServiceRecord::list(tmp.path()).unwrap();
+        assert_eq!(records, [record]);
+        assert!(!records[0].is_running());
+
+        ServiceRecord::delete(tmp.path(), "user/server").unwrap();
+        assert!(ServiceRecord::list(tmp.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use thiserror::Error;
+
+/// First line after the shebang, marks files we generated and the package they launch
+const MARKER: &str = "run-that shim for ";
+
+#[derive(Error, Debug)]
+pub enum ShimError {
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("`{name}` would shadow {existing:?}, choose another name with --as")]
+    Conflict { name: String, existing: PathBuf },
+    #[error("`{name}` already launches `{id}`, remove it first")]
+    Taken { name: String, id: String },
+    #[error("invalid shim name `{0}`")]
+    InvalidName(String),
+}
+
+/// Launcher script in the bin directory
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Shim {
+    pub name: String,
+    pub id: String, // Package the shim launches
+    pub path: PathBuf,
+}
+
+pub fn shim_path(bin: &Path, name: &str) -> PathBuf {
+    if cfg!(windows) {
+        bin.join(format!("{name}.cmd"))
+    } else {
+        bin.join(name)
+    }
+}
+
+/// Launcher of package `id`, running `exe` with the data directory `home`
+fn script(id: &str, exe: &Path, home: &Path) -> String {
+    if cfg!(windows) {
+        format!(
+            "@echo off\r\nrem {MARKER}{id}\r\nset \"RUN_THAT_HOME={}\"\r\n\"{}\" run \"{id}\" %*\r\n",
+            home.display(),
+            exe.display()
+        )
+    } else {
+        format!(
+            "#!/bin/sh\n# {MARKER}{id}\nRUN_THAT_HOME={}\nexport RUN_THAT_HOME\nexec {} run {} \"$@\"\n",
+            quote(&home.display().to_string()),
+            quote(&exe.display().to_string()),
+            quote(id)
+        )
+    }
+}
+
+/// Single quoted shell word
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Reads the package id from a shim, `None` if the file is not a shim
+fn read_shim(path: &Path) -> Option<String> {
+    let text = std::fs::read_to_string(path).ok()?;
+    text.lines()
+        .take(2)
+        .find_map(|l| l.split_once(MARKER))
+        .map(|(_, id)| id.trim().to_string())
+}
+
+/// Finds an executable called `name` on `PATH`, ignoring the `bin` directory itself
+pub fn find_on_path(name: &str, bin: &Path) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    let extensions: &[&str] = if cfg!(windows) {
+        &["", ".exe", ".cmd", ".bat"]
+    } else {
+        &[""]
+    };
+
+    std::env::split_paths(&path)
+        .filter(|dir| dir != bin)
+        .flat_map(|dir| {
+            extensions
+                .iter()
+                .map(move |ext| dir.join(format!("{name}{ext}")))
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+/// Whether `dir` is one of the directories on `PATH`
+pub fn on_path(dir: &Path) -> bool {
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|p| p == dir))
+        .unwrap_or(false)
+}
+
+/// Creates a shim `name` in `bin` that launches package `id` through `exe`, with `home`
+/// as the data directory.
+///
+/// Fails if the name would shadow another program or is taken by a shim of another
+/// package, unless `force` is set.
+pub fn create(
+    bin: &Path,
+    name: &str,
+    id: &str,
+    exe: &Path,
+    home: &Path,
+    force: bool,
+) -> Result<Shim, ShimError> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(ShimError::InvalidName(name.to_string()));
+    }
+
+    let path = shim_path(bin, name);
+    if !force {
+        if path.exists() {
+            match read_shim(&path) {
+                Some(existing) if existing == id => {}
+                Some(existing) => {
+                    return Err(ShimError::Taken {
+                        name: name.to_string(),
+                        id: existing,
+                    })
+                }
+                None => {
+                    return Err(ShimError::Conflict {
+                        name: name.to_string(),
+                        existing: path,
+                    })
+                }
+            }
+        }
+
+        if let Some(existing) = find_on_path(name, bin) {
+            return Err(ShimError::Conflict {
+                name: name.to_string(),
+                existing,
+            });
+        }
+    }
+
+    std::fs::create_dir_all(bin)?;
+    std::fs::write(&path, script(id, exe, home))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(Shim {
+        name: name.to_string(),
+        id: id.to_string(),
+        path,
+    })
+}
+
+pub fn list(bin: &Path) -> Result<Vec<Shim>, std::io::Error> {
+    let mut shims = vec![];
+    if !bin.exists() {
+        return Ok(shims);
+    }
+
+    for entry in std::fs::read_dir(bin)? {
+        let path = entry?.path();
+        if let Some(id) = read_shim(&path) {
+            let name = path
+                .file_stem()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            shims.push(Shim { name, id, path });
+        }
+    }
+
+    shims.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(shims)
+}
+
+/// Removes all shims launching package `id`, returning them
+pub fn remove_for(bin: &Path, id: &str) -> Result<Vec<Shim>, std::io::Error> {
+    let shims: Vec<_> = list(bin)?.into_iter().filter(|s| s.id == id).collect();
+    for shim in &shims {
+        std::fs::remove_file(&shim.path)?;
+    }
+
+    Ok(shims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_list_remove() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = tmp.path().join("bin");
+        let exe = Path::new("/usr/bin/run-that");
+        let home = tmp.path();
+
+        let shim = create(&bin, "run-that-test-tool", "user/tool", exe, home, false).unwrap();
+        assert_eq!(read_shim(&shim.path).as_deref(), Some("user/tool"));
+        assert_eq!(list(&bin).unwrap(), std::slice::from_ref(&shim));
+
+        // Linking the same package again is fine, another one is not
+        assert!(create(&bin, "run-that-test-tool", "user/tool", exe, home, false).is_ok());
+        assert!(matches!(
+            create(&bin, "run-that-test-tool", "user/other", exe, home, false),
+            Err(ShimError::Taken { .. })
+        ));
+
+        assert_eq!(remove_for(&bin, "user/tool").unwrap(), [shim]);
+        assert!(list(&bin).unwrap().is_empty());
+    }
+
+    #[test]
+    fn conflicts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = tmp.path().join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(shim_path(&bin, "foreign"), "not a shim").unwrap();
+        let exe = Path::new("run-that");
+        let home = tmp.path();
+
+        assert!(matches!(
+            create(&bin, "foreign", "user/tool", exe, home, false),
+            Err(ShimError::Conflict { .. })
+        ));
+        assert!(matches!(
+            create(&bin, "../escape", "user/tool", exe, home, false),
+            Err(ShimError::InvalidName(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quoted_launch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = tmp.path().join("bin");
+        let home = tmp.path().join("it's home");
+        // Stands in for run-that, records what the shim passed on
+        let exe = tmp.path().join("fake run-that");
+        std::fs::write(
+            &exe,
+            "#!/bin/sh\nprintf '%s|' \"$RUN_THAT_HOME\" \"$@\" > \"$(dirname \"$0\")/launched\"\n",
+        )
+        .unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let shim = create(&bin, "tool", "user/it's", &exe, &home, false).unwrap();
+        assert_eq!(read_shim(&shim.path).as_deref(), Some("user/it's"));
+        let status = std::process::Command::new(&shim.path)
+            .arg("a b")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("launched")).unwrap(),
+            format!("{}|run|user/it's|a b|", home.display())
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::package_index::PackageSource;
+
+#[derive(Error, Debug)]
+pub enum StateError {
+    #[error("could not access install record")]
+    Io(#[from] std::io::Error),
+    #[error("install record is corrupted")]
+    Parse(#[from] serde_yaml::Error),
+}
+
+/// Where an installed package came from
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceRecord {
+    Git(String),    // Address the package was installed from
+    Local(PathBuf), // Directory the package was copied from
+    Archive {
+        location: String,       // Path or URL of the archive
+        sha256: Option<String>, // Checksum the archive was pinned to
+    },
+}
+
+impl From<&PackageSource> for SourceRecord {
+    fn from(source: &PackageSource) -> Self {
+        if let Some(git) = source.git() {
+            SourceRecord::Git(git.to_string())
+        } else if let Some(path) = source.local() {
+            SourceRecord::Local(path.to_path_buf())
+        } else if let Some(archive) = source.archive() {
+            SourceRecord::Archive {
+                location: archive.location().to_string(),
+                sha256: archive.sha256().map(str::to_string),
+            }
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+/// Install record of a package, stored in `MAIN_PATH/state/<id>.yml`
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+pub struct InstallRecord {
+    pub id: String,             // Directory of the package inside the repos directory
+    pub source: SourceRecord,   // Where the package was installed from
+    pub commit: Option<String>, // Checked out commit, if the package is a git repo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>, // Tag the version range of the address resolved to
+    pub installed_at: u64,      // Unix timestamp of the install
+    pub updated_at: u64,        // Unix timestamp of the last install or update
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub init_fingerprints: Vec<String>, // Init steps that succeeded, see `crate::fingerprint`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<String>, // Files of a local or archive package before init, see `fingerprint::tree`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<Revision>, // Commits checked out so far, the current one last
+}
+
+/// Commit a git package was installed, updated or rolled back to
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+pub struct Revision {
+    pub commit: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>, // Tag the version range resolved to at the time
+    pub at: u64, // Unix timestamp of the checkout
+}
+
+/// Revisions kept in the history of a package, older ones are forgotten
+const HISTORY_LIMIT: usize = 20;
+
+impl InstallRecord {
+    pub fn new(id: String, source: SourceRecord, commit: Option<String>) -> Self {
+        let now = now();
+        Self {
+            id,
+            source,
+            commit,
+            tag: None,
+            installed_at: now,
+            updated_at: now,
+            init_fingerprints: vec![],
+            tree: None,
+            history: vec![],
+        }
+    }
+
+    /// Appends the checked out commit to the history, unless it's already the latest revision
+    pub fn push_revision(&mut self) {
+        let Some(commit) = &self.commit else {
+            return;
+        };
+
+        let revision = Revision {
+            commit: commit.clone(),
+            tag: self.tag.clone(),
+            at: self.updated_at,
+        };
+        match self.history.last_mut() {
+            Some(last) if last.commit == revision.commit => *last = revision,
+            _ => self.history.push(revision),
+        }
+
+        let extra = self.history.len().saturating_sub(HISTORY_LIMIT);
+        self.history.drain(..extra);
+    }
+
+    pub fn path(state_dir: &Path, id: &str) -> PathBuf {
+        state_dir.join(format!("{id}.yml"))
+    }
+
+    pub fn load(state_dir: &Path, id: &str) -> Result<Option<Self>, StateError> {
+        let path = Self::path(state_dir, id);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = std::fs::File::open(path)?;
+        Ok(Some(serde_yaml::from_reader(file)?))
+    }
+
+    pub fn save(&self, state_dir: &Path) -> Result<(), StateError> {
+        let path = Self::path(state_dir, &self.id);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn delete(state_dir: &Path, id: &str) -> Result<(), StateError> {
+        let path = Self::path(state_dir, id);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Id of the version of `package` (`user/name`) chosen with `run-that use`
+pub fn active_version(state_dir: &Path, package: &str) -> Result<Option<String>, StateError> {
+    match std::fs::read_to_string(active_path(state_dir, package)) {
+        Ok(id) => Ok(Some(id.trim().to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Chooses the version of `package` used when it's named without one, `None` forgets the choice
+pub fn set_active_version(
+    state_dir: &Path,
+    package: &str,
+    id: Option<&str>,
+) -> Result<(), StateError> {
+    let path = active_path(state_dir, package);
+    match id {
+        Some(id) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, id)?;
+        }
+        None if path.exists() => std::fs::remove_file(path)?,
+        None => {}
+    }
+
+    Ok(())
+}
+
+fn active_path(state_dir: &Path, package: &str) -> PathBuf {
+    state_dir.join(format!("{package}.active"))
+}
+
+/// Current unix timestamp in seconds
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = SourceRecord::Git("github.com:u/r".to_string());
+        let record = InstallRecord::new("u/r".to_string(), source, Some("abc".to_string()));
+        record.save(tmp.path()).unwrap();
+        assert!(tmp.path().join("u/r.yml").exists());
+        assert_eq!(
+            InstallRecord::load(tmp.path(), "u/r").unwrap(),
+            Some(record)
+        );
+
+        InstallRecord::delete(tmp.path(), "u/r").unwrap();
+        assert_eq!(InstallRecord::load(tmp.path(), "u/r").unwrap(), None);
+        InstallRecord::delete(tmp.path(), "u/r").unwrap();
+    }
+
+    #[test]
+    fn revisions() {
+        let mut record =
+            InstallRecord::new("u/r".to_string(), SourceRecord::Local(".".into()), None);
+        record.push_revision();
+        assert!(record.history.is_empty());
+
+        for i in 0..HISTORY_LIMIT + 5 {
+            record.commit = Some(i.to_string());
+            record.push_revision();
+            // The same commit again only updates the latest revision
+            record.push_revision();
+        }
+        assert_eq!(record.history.len(), HISTORY_LIMIT);
+        assert_eq!(record.history[0].commit, "5");
+        assert_eq!(
+            record.history.last().unwrap().commit,
+            (HISTORY_LIMIT + 4).to_string()
+        );
+    }
+
+    #[test]
+    fn active_versions() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(active_version(tmp.path(), "u/r").unwrap(), None);
+        set_active_version(tmp.path(), "u/r", Some("u/r@v1")).unwrap();
+        assert_eq!(
+            active_version(tmp.path(), "u/r").unwrap().as_deref(),
+            Some("u/r@v1")
+        );
+        set_active_version(tmp.path(), "u/r", None).unwrap();
+        set_active_version(tmp.path(), "u/r", None).unwrap();
+        assert_eq!(active_version(tmp.path(), "u/r").unwrap(), None);
+    }
+}
@@ -0,0 +1,413 @@
+use std::{collections::BTreeMap, fmt, marker::PhantomData};
+
+use once_cell::sync::Lazy;
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid target selector `{selector}`: {reason}")]
+pub struct SelectorError {
+    pub selector: String,
+    pub reason: String,
+}
+
+/// Facts about a platform that selectors are matched against
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Target {
+    pub os: String,                     // `target_os`, e.g. `linux` or `freebsd`
+    pub family: String,                 // `target_family`, `unix` or `windows`
+    pub arch: String,                   // `target_arch`, e.g. `x86_64` or `aarch64`
+    pub distro: Option<String>,         // `ID` from `/etc/os-release`, e.g. `debian`
+    pub distro_like: Vec<String>,       // `ID_LIKE` from `/etc/os-release`
+    pub distro_version: Option<String>, // `VERSION_ID` from `/etc/os-release`
+}
+
+static CURRENT: Lazy<Target> = Lazy::new(|| {
+    let release = std::fs::read_to_string("/etc/os-release")
+        .or_else(|_| std::fs::read_to_string("/usr/lib/os-release"))
+        .map(|text| parse_os_release(&text))
+        .unwrap_or_default();
+
+    Target {
+        os: std::env::consts::OS.to_string(),
+        family: std::env::consts::FAMILY.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        distro: release.get("ID").cloned(),
+        distro_like: release
+            .get("ID_LIKE")
+            .map(|l| l.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default(),
+        distro_version: release.get("VERSION_ID").cloned(),
+    }
+});
+
+impl Target {
+    /// The platform we are running on
+    pub fn current() -> &'static Target {
+        &CURRENT
+    }
+}
+
+fn parse_os_release(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            (key.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// Short names accepted on their own, like `linux` in `cfg(all(linux, unix))`
+const NAMES: &[(&str, &str, &str)] = &[
+    // (name, key, value)
+    ("win", "target_os", "windows"),
+    ("windows", "target_os", "windows"),
+    ("mac", "target_os", "macos"),
+    ("macos", "target_os", "macos"),
+    ("linux", "target_os", "linux"),
+    ("freebsd", "target_os", "freebsd"),
+    ("openbsd", "target_os", "openbsd"),
+    ("netbsd", "target_os", "netbsd"),
+    ("android", "target_os", "android"),
+    ("unix", "target_family", "unix"),
+];
+
+const KEYS: &[&str] = &[
+    "target_os",
+    "target_family",
+    "target_arch",
+    "distro",
+    "distro_like",
+    "distro_version",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    All(Vec<Expr>),
+    Any(Vec<Expr>),
+    Not(Box<Expr>),
+    Is(String, String), // key = "value"
+}
+
+impl Expr {
+    fn matches(&self, target: &Target) -> bool {
+        match self {
+            Expr::All(exprs) => exprs.iter().all(|e| e.matches(target)),
+            Expr::Any(exprs) => exprs.iter().any(|e| e.matches(target)),
+            Expr::Not(expr) => !expr.matches(target),
+            Expr::Is(key, value) => match key.as_str() {
+                "target_os" => target.os == *value,
+                "target_family" => target.family == *value,
+                "target_arch" => target.arch == *value,
+                "distro" => target.distro.as_ref() == Some(value),
+                "distro_like" => {
+                    target.distro.as_ref() == Some(value) || target.distro_like.contains(value)
+                }
+                "distro_version" => target.distro_version.as_ref() == Some(value),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Cfg-style condition on the target, like `cfg(all(linux, target_arch = "aarch64"))`.
+///
+/// Keys are `target_os`, `target_family`, `target_arch` and `distro`, `distro_like`
+/// and `distro_version` read from `/etc/os-release`. OS names like `linux` or `win`
+/// can be used on their own, and combined with `all`, `any` and `not`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    source: String,
+    expr: Expr,
+}
+
+impl Selector {
+    pub fn parse(source: &str) -> Result<Self, SelectorError> {
+        let error = |reason: &str| SelectorError {
+            selector: source.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let inner = source
+            .trim()
+            .strip_prefix("cfg(")
+            .and_then(|s| s.strip_suffix(')'))
+            .ok_or_else(|| error("expected `cfg(...)`"))?;
+
+        let mut parser = Parser {
+            tokens: tokenize(inner).map_err(error)?,
+            pos: 0,
+        };
+        let expr = parser.expr().map_err(error)?;
+        if parser.pos != parser.tokens.len() {
+            return Err(error("unexpected input after the condition"));
+        }
+
+        Ok(Self {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn matches(&self, target: &Target) -> bool {
+        self.expr.matches(target)
+    }
+
+    /// Whether `key` looks like a selector rather than a plain field
+    pub fn is_selector(key: &str) -> bool {
+        key.trim_start().starts_with("cfg(")
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Open,
+    Close,
+    Comma,
+    Eq,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            '=' => tokens.push(Token::Eq),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err("unterminated string"),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_whitespace() => {}
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            _ => return Err("unexpected character"),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expect(&mut self, token: Token, reason: &'static str) -> Result<(), &'static str> {
+        if self.next() == Some(token) {
+            Ok(())
+        } else {
+            Err(reason)
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, &'static str> {
+        let Some(Token::Ident(ident)) = self.next() else {
+            return Err("expected a name");
+        };
+
+        match (ident.as_str(), self.peek()) {
+            ("all" | "any" | "not", Some(Token::Open)) => {
+                self.pos += 1;
+                let mut exprs = vec![];
+                while self.peek() != Some(&Token::Close) {
+                    exprs.push(self.expr()?);
+                    if self.peek() == Some(&Token::Comma) {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::Close, "expected `)`")?;
+
+                match ident.as_str() {
+                    "all" => Ok(Expr::All(exprs)),
+                    "any" => Ok(Expr::Any(exprs)),
+                    _ if exprs.len() == 1 => Ok(Expr::Not(Box::new(exprs.remove(0)))),
+                    _ => Err("`not` takes exactly one condition"),
+                }
+            }
+            (key, Some(Token::Eq)) => {
+                if !KEYS.contains(&key) {
+                    return Err("unknown key");
+                }
+                self.pos += 1;
+                match self.next() {
+                    Some(Token::Str(value)) => Ok(Expr::Is(key.to_string(), value)),
+                    _ => Err("expected a quoted value"),
+                }
+            }
+            (name, _) => NAMES
+                .iter()
+                .find(|(n, _, _)| *n == name)
+                .map(|(_, key, value)| Expr::Is(key.to_string(), value.to_string()))
+                .ok_or("unknown name"),
+        }
+    }
+}
+
+/// Values keyed by selectors, in the order they were written in the manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Targets<T>(Vec<(Selector, T)>);
+
+impl<T> Default for Targets<T> {
+    fn default() -> Self {
+        Self(vec![])
+    }
+}
+
+impl<T> Targets<T> {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, selector: Selector, value: T) {
+        self.0.push((selector, value));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Selector, T)> {
+        self.0.iter()
+    }
+
+    /// Values whose selector matches `target`, in manifest order
+    pub fn matching<'a>(&'a self, target: &'a Target) -> impl Iterator<Item = &'a T> {
+        self.0
+            .iter()
+            .filter(move |(s, _)| s.matches(target))
+            .map(|(_, v)| v)
+    }
+}
+
+impl<T: Serialize> Serialize for Targets<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(s, v)| (s.to_string(), v)))
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Targets<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TargetsVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for TargetsVisitor<T> {
+            type Value = Targets<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of target selectors")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut targets = Targets::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    let selector = Selector::parse(&key).map_err(serde::de::Error::custom)?;
+                    targets.push(selector, map.next_value()?);
+                }
+                Ok(targets)
+            }
+        }
+
+        deserializer.deserialize_map(TargetsVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(os: &str, arch: &str, distro: Option<&str>) -> Target {
+        Target {
+            os: os.to_string(),
+            family: if os == "windows" { "windows" } else { "unix" }.to_string(),
+            arch: arch.to_string(),
+            distro: distro.map(str::to_string),
+            distro_like: vec!["debian".to_string()],
+            distro_version: None,
+        }
+    }
+
+    #[test]
+    fn parse_and_match() {
+        let pi = target("linux", "aarch64", Some("raspbian"));
+        let fedora = target("linux", "x86_64", Some("fedora"));
+        let bsd = target("freebsd", "x86_64", None);
+
+        let arm = Selector::parse(r#"cfg(all(linux, target_arch = "aarch64"))"#).unwrap();
+        assert!(arm.matches(&pi));
+        assert!(!arm.matches(&fedora));
+
+        let debian = Selector::parse(r#"cfg(distro_like = "debian")"#).unwrap();
+        assert!(debian.matches(&pi));
+
+        let not_linux = Selector::parse("cfg(all(unix, not(linux)))").unwrap();
+        assert!(not_linux.matches(&bsd));
+        assert!(!not_linux.matches(&fedora));
+
+        let any = Selector::parse(r#"cfg(any(win, distro = "fedora"))"#).unwrap();
+        assert!(any.matches(&fedora));
+        assert!(!any.matches(&bsd));
+    }
+
+    #[test]
+    fn invalid() {
+        for source in [
+            "linux",
+            "cfg(solaris)",
+            r#"cfg(target_cpu = "x")"#,
+            "cfg(not(linux, win))",
+            "cfg(all(linux)",
+            r#"cfg(target_os = "linux)"#,
+            "cfg(linux win)",
+        ] {
+            assert!(Selector::parse(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn os_release() {
+        let release = parse_os_release("ID=debian\nVERSION_ID=\"12\"\nID_LIKE='rhel fedora'\n");
+        assert_eq!(release["ID"], "debian");
+        assert_eq!(release["VERSION_ID"], "12");
+        assert_eq!(release["ID_LIKE"], "rhel fedora");
+    }
+}
@@ -0,0 +1,101 @@
+//! Git repositories and managers shared by tests that install packages.
+
+use std::path::Path;
+
+use git2::{Repository, Signature};
+use tempfile::TempDir;
+
+use crate::{
+    config::Config,
+    manager::{Manager, Options},
+    package_index::PackageSource,
+};
+
+/// Manifest of a runnable test package, with an init step run by `sh -c` if given
+pub(crate) fn manifest(name: &str, init: Option<&str>) -> String {
+    let init = init
+        .map(|init| format!("init:\n  global:\n    - program: sh\n      args: [-c, \"{init}\"]\n"))
+        .unwrap_or_default();
+    format!(
+        "name: {name}\ndescription: test\nauthors: []\n{init}run:\n  default:\n    program: tool\n"
+    )
+}
+
+/// Repositories in a temporary directory that install as `u/<name>`, with a manager
+/// whose data directory is in there too
+pub(crate) struct Remotes {
+    pub(crate) dir: TempDir,
+    pub(crate) config: Config, // Rewrites `github.com/u/` to the directory
+}
+
+impl Remotes {
+    pub(crate) fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config
+            .set(
+                "url_rewrites.https://github.com/u/",
+                &format!("file://{}/", dir.path().display()),
+            )
+            .unwrap();
+        Self { dir, config }
+    }
+
+    /// Creates the repository of `u/<name>` with `manifest` as `run.yml`
+    pub(crate) fn repo(&self, name: &str, manifest: &str) -> Repository {
+        let path = self.dir.path().join(name);
+        source_repo(&path);
+        let repo = Repository::open(path).unwrap();
+        commit(&repo, "run.yml", manifest);
+        repo
+    }
+
+    pub(crate) fn manager(&self) -> Manager {
+        Manager::new(self.dir.path().join("home"))
+            .unwrap()
+            .with_config(self.config.clone())
+            .with_options(Options {
+                quiet: true,
+                offline: false,
+            })
+    }
+
+    pub(crate) fn source(&self, address: &str) -> PackageSource {
+        PackageSource::new_git(address.to_string(), &self.config).unwrap()
+    }
+}
+
+/// Tags the head of `repo`, returning the tagged commit
+pub(crate) fn tag(repo: &Repository, name: &str) -> String {
+    let head = repo.head().unwrap().peel(git2::ObjectType::Commit).unwrap();
+    repo.tag_lightweight(name, &head, false).unwrap();
+    head.id().to_string()
+}
+
+/// Creates a repository with one commit on `main`, returning its `file://` url
+pub(crate) fn source_repo(dir: &Path) -> String {
+    let repo = Repository::init(dir).unwrap();
+    repo.set_head("refs/heads/main").unwrap();
+    commit(&repo, "run.yml", "name: x");
+    format!("file://{}", dir.display())
+}
+
+/// Writes `file` and commits it on top of `HEAD`, the content is the commit message too
+pub(crate) fn commit(repo: &Repository, file: &str, content: &str) -> git2::Oid {
+    let dir = repo.workdir().unwrap();
+    std::fs::write(dir.join(file), content).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new(file)).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = Signature::now("test", "test@example.com").unwrap();
+    let parents: Vec<_> = repo
+        .head()
+        .ok()
+        .and_then(|h| h.peel_to_commit().ok())
+        .into_iter()
+        .collect();
+    let parents: Vec<_> = parents.iter().collect();
+    repo.commit(Some("HEAD"), &sig, &sig, content, &tree, &parents)
+        .unwrap()
+}
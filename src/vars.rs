@@ -0,0 +1,157 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum VarError {
+    #[error("unknown variable `${{{0}}}`")]
+    Unknown(String),
+    #[error("unterminated variable in `{0}`")]
+    Unterminated(String),
+    #[error("environment variable `{0}` is not set")]
+    MissingEnv(String),
+    #[error("parameter `{0}` was not given, pass it with `--param {0}=<value>`")]
+    MissingParam(String),
+}
+
+/// Values of the variables that can be used in commands, like `${PACKAGE_DIR}`.
+///
+/// `$$` is a literal `$`, any other `$` not followed by `{` is left alone.
+#[derive(Debug, Clone)]
+pub struct Vars {
+    package_dir: PathBuf,
+    home: PathBuf,                    // Data directory, `${RUN_THAT_HOME}`
+    params: BTreeMap<String, String>, // Values of `${param:NAME}`
+}
+
+impl Vars {
+    pub fn new(package_dir: impl Into<PathBuf>, home: impl Into<PathBuf>) -> Self {
+        Self {
+            package_dir: package_dir.into(),
+            home: home.into(),
+            params: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_params(mut self, params: BTreeMap<String, String>) -> Self {
+        self.params = params;
+        self
+    }
+
+    fn resolve(&self, name: &str) -> Result<String, VarError> {
+        match parse(name)? {
+            Var::PackageDir => Ok(self.package_dir.display().to_string()),
+            Var::Home => Ok(self.home.display().to_string()),
+            Var::Os => Ok(std::env::consts::OS.to_string()),
+            Var::Arch => Ok(std::env::consts::ARCH.to_string()),
+            Var::Env(key) => std::env::var(key).map_err(|_| VarError::MissingEnv(key.to_string())),
+            Var::Param(key) => self
+                .params
+                .get(key)
+                .cloned()
+                .ok_or_else(|| VarError::MissingParam(key.to_string())),
+        }
+    }
+
+    pub fn package_dir(&self) -> &Path {
+        &self.package_dir
+    }
+
+    /// Replaces all variables in `text` with their values
+    pub fn expand(&self, text: &str) -> Result<String, VarError> {
+        interpolate(text, |name| self.resolve(name))
+    }
+}
+
+enum Var<'a> {
+    PackageDir,
+    Home,
+    Os,
+    Arch,
+    Env(&'a str),
+    Param(&'a str),
+}
+
+fn parse(name: &str) -> Result<Var<'_>, VarError> {
+    match name.split_once(':') {
+        None => match name {
+            "PACKAGE_DIR" => Ok(Var::PackageDir),
+            "RUN_THAT_HOME" => Ok(Var::Home),
+            "OS" => Ok(Var::Os),
+            "ARCH" => Ok(Var::Arch),
+            _ => Err(VarError::Unknown(name.to_string())),
+        },
+        Some(("env", key)) if !key.is_empty() => Ok(Var::Env(key)),
+        Some(("param", key)) if !key.is_empty() => Ok(Var::Param(key)),
+        Some(_) => Err(VarError::Unknown(name.to_string())),
+    }
+}
+
+/// Checks that `text` uses only known variables, without resolving them
+pub fn lint(text: &str) -> Result<(), VarError> {
+    interpolate(text, |name| parse(name).map(|_| String::new())).map(|_| ())
+}
+
+fn interpolate(
+    text: &str,
+    mut resolve: impl FnMut(&str) -> Result<String, VarError>,
+) -> Result<String, VarError> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        if let Some(after) = after.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = after.strip_prefix('{') {
+            let end = after
+                .find('}')
+                .ok_or_else(|| VarError::Unterminated(text.to_string()))?;
+            result.push_str(&resolve(&after[..end])?);
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+            rest = after;
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand() {
+        let vars = Vars::new("/pkg", "/home")
+            .with_params([("port".to_string(), "8080".to_string())].into());
+
+        assert_eq!(
+            vars.expand("${PACKAGE_DIR}/bin:${param:port}").unwrap(),
+            "/pkg/bin:8080"
+        );
+        assert_eq!(vars.expand("${RUN_THAT_HOME}").unwrap(), "/home");
+        assert_eq!(vars.expand("${OS}").unwrap(), std::env::consts::OS);
+        assert_eq!(vars.expand("$$HOME costs $5").unwrap(), "$HOME costs $5");
+        assert_eq!(
+            vars.expand("${param:host}"),
+            Err(VarError::MissingParam("host".to_string()))
+        );
+    }
+
+    #[test]
+    fn lint_unknown() {
+        assert!(lint("${env:PATH} ${ARCH} $${NOPE}").is_ok());
+        assert_eq!(lint("${NOPE}"), Err(VarError::Unknown("NOPE".to_string())));
+        assert_eq!(lint("${env:}"), Err(VarError::Unknown("env:".to_string())));
+        assert!(matches!(lint("${OS"), Err(VarError::Unterminated(_))));
+    }
+}